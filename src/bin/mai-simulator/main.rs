@@ -19,7 +19,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     let mut mcx = maidata::materialize::MaterializationContext::with_offset(0.0);
     let mut notes = mcx
-        .materialize_insns(&notes)
+        .materialize_chart(&notes, &[], None)
+        .notes
         .into_iter()
         .map(|note| Note::try_from((*note).clone()))
         .collect::<Result<Vec<Note>, _>>()?;
@@ -1,23 +1,5 @@
-use maidata::insn::RawInsn;
 use std::ops::Deref;
 
-fn print_raw_insn(insn: &RawInsn) {
-    match insn {
-        RawInsn::Bpm(params) => print!("({})", params),
-        RawInsn::BeatDivisor(params) => print!("{{{}}}", params),
-        RawInsn::Rest => print!(","),
-        RawInsn::Notes(note_bundle) => print!(
-            "{},",
-            note_bundle
-                .iter()
-                .map(|x| format!("{}", x.deref()))
-                .collect::<Vec<_>>()
-                .join("/")
-        ),
-        RawInsn::EndMark => print!("E"),
-    }
-}
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let filename = std::env::args()
         .nth(1)
@@ -25,11 +7,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let content = read_file(filename);
     let (maidata, state) = maidata::container::lex_maidata(&content);
     // assert!(!state.has_messages());
-    for error in &state.errors {
-        eprintln!("Error: {}", error);
-    }
-    for warning in &state.warnings {
-        eprintln!("Warning: {}", warning);
+    if state.has_messages() {
+        use std::io::IsTerminal;
+
+        // messages go to stderr, so that is what decides the colours
+        let renderer = maidata::Renderer::new(&content).color(std::io::stderr().is_terminal());
+        eprintln!("{}", renderer.render_state(&state));
     }
 
     println!("title = {}", maidata.title());
@@ -56,12 +39,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             diff.single_message().unwrap_or("<not set>")
         );
 
-        let mut mcx = maidata::materialize::MaterializationContext::with_offset(0.0);
-        let notes = mcx.materialize_insns(diff.iter_insns());
-        println!("  <{} notes materialized>", notes.len());
+        let chart = diff.materialize();
+        println!(
+            "  <{} notes materialized, ending at {}s>",
+            chart.notes.len(),
+            chart.end_ts
+        );
 
         for insn in diff.iter_insns() {
-            print_raw_insn(insn.deref());
+            print!("{}", insn.deref());
         }
     }
 
@@ -50,6 +50,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }).collect::<Vec<_>>(),
         "warnings": messages_to_value(&state.warnings),
         "errors": messages_to_value(&state.errors),
+        "diagnostics": state.diagnostics(),
     });
     let json_str = serde_json::to_string_pretty(&json).expect("serializing json failed");
     std::fs::write(output, json_str).expect("writing json file failed");
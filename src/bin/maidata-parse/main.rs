@@ -25,17 +25,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 fn parse_maidata<P: AsRef<std::path::Path>>(path: P) -> Result<(), Box<dyn std::error::Error>> {
     let content = read_file(&path);
-    let (_, state) = maidata::container::lex_maidata(&content);
+    let state = match maidata::container::try_lex_maidata(&content) {
+        Ok((_, state)) => state,
+        Err(e) => {
+            println!("Path: {:?}", path.as_ref());
+            println!("{}", e);
+            return Ok(());
+        }
+    };
 
     if state.has_messages() {
+        use std::io::IsTerminal;
+
         println!("Path: {:?}", path.as_ref());
-        for warning in &state.warnings {
-            println!("Warning: {}", warning);
-        }
-        for error in &state.errors {
-            println!("Error: {}", error);
-        }
-        println!();
+        let renderer = maidata::Renderer::new(&content).color(std::io::stdout().is_terminal());
+        println!("{}", renderer.render_state(&state));
     }
 
     // for diff in maidata.iter_difficulties() {
@@ -135,10 +135,9 @@ fn parse_maidata(diff: &AssociatedBeatmapData) -> Option<Vec<Vec<Note>>> {
     //     return None;
     // }
 
-    let mut mcx = maidata::materialize::MaterializationContext::with_offset(0.0);
-    let notes = mcx.materialize_insns(diff.iter_insns());
-
-    let mut notes = notes
+    let mut notes = diff
+        .materialize()
+        .notes
         .into_iter()
         .map(|note| (*note).clone())
         .map(|note| match &note {
@@ -48,6 +48,7 @@ fn main() {
             let charts = parse_maidata(entry.path(), |diff| match diff.level() {
                 Some(Level::Normal(level)) => (11..=13).contains(&level),
                 Some(Level::Plus(level)) => (11..=13).contains(&level),
+                Some(Level::Decimal(level)) => (110..140).contains(&level),
                 Some(Level::Char(_)) => false,
                 None => false,
             });
@@ -73,7 +74,7 @@ fn main() {
     }
 
     let mut result: Vec<_> = group_map.iter().map(|(&k, &v)| (v, k.clone())).collect();
-    result.sort_by(|&(k1, _), &(k2, _)| k2.cmp(&k1));
+    result.sort_by_key(|&(k, _)| std::cmp::Reverse(k));
     result.retain(|(_, group)| {
         group.iter().any(|bundle| {
             bundle
@@ -132,6 +133,16 @@ where
                         result.sort();
                         Some(result)
                     }
+                    // the groups are hit together, so count them as one bundle
+                    RawInsn::PseudoEach(groups) => {
+                        let mut result: Vec<_> = groups
+                            .iter()
+                            .flatten()
+                            .map(|x| normalize_note(x.deref()).unwrap())
+                            .collect();
+                        result.sort();
+                        Some(result)
+                    }
                     _ => None,
                 })
                 .collect();
@@ -0,0 +1,452 @@
+//! Keeping a lexed file up to date with edits without parsing every note string again.
+
+use super::{lex_maidata_with, note_key_difficulty, try_lex_maidata, ContainerError, Maidata};
+use crate::insn::RawInsn;
+use crate::parser::{parse_one_maidata_insn, NomSpan, PError, PResult, PWarning, State};
+use crate::{Difficulty, Sp, Span};
+use std::ops::Range;
+
+/// A change to a text: the bytes in `range` are replaced by `text`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// What [`IncrementalMaidata::edit`] had to parse again.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Reparsed {
+    /// The edit may have changed the keys, so the whole file was lexed again.
+    File,
+    /// Only metadata values changed, all instructions were kept.
+    Metadata,
+    /// The part of the note string of `difficulty` at `span` was parsed again, the instructions
+    /// before and after it were kept.
+    Notes { difficulty: Difficulty, span: Span },
+}
+
+/// A lexed `maidata.txt` kept up to date with edits, e.g. in an editor with live preview.
+///
+/// An edit within a metadata value only lexes the key-value pairs again, which is cheap. If the
+/// value is a note string, parsing restarts shortly before the edit, and stops at the first
+/// instruction after it that starts at the same place as before. All other instructions and
+/// their messages are kept with their spans moved. Edits that may change the keys, like adding
+/// or removing a `&`, lex the whole file again.
+///
+/// The result is the same as lexing the edited text with [`try_lex_maidata`].
+#[derive(Clone, Debug)]
+pub struct IncrementalMaidata {
+    source: String,
+    maidata: Maidata,
+    state: State,
+}
+
+impl IncrementalMaidata {
+    pub fn new<S: Into<String>>(source: S) -> Result<Self, ContainerError> {
+        let source = source.into();
+        let (maidata, state) = try_lex_maidata(&source)?;
+        Ok(Self {
+            source,
+            maidata,
+            state,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn maidata(&self) -> &Maidata {
+        &self.maidata
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn into_inner(self) -> (String, Maidata, State) {
+        (self.source, self.maidata, self.state)
+    }
+
+    /// Apply `edit` to the source and update the parse result. Nothing is changed on error.
+    ///
+    /// # Panics
+    ///
+    /// Like [`String::replace_range`], if the range is out of bounds or not on char boundaries.
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<Reparsed, ContainerError> {
+        let mut source = self.source.clone();
+        source.replace_range(edit.range.clone(), &edit.text);
+
+        let value_span = match self.edited_value(edit) {
+            Some(x) => x,
+            None => {
+                let (maidata, state) = try_lex_maidata(&source)?;
+                *self = Self {
+                    source,
+                    maidata,
+                    state,
+                };
+                return Ok(Reparsed::File);
+            }
+        };
+        let edited_difficulty = self
+            .maidata
+            .metadata()
+            .iter()
+            .find(|x| x.value().span() == value_span)
+            .and_then(|x| note_key_difficulty(x.key()));
+
+        let shift = Shift::new(&self.source, &source, edit);
+        let mut reparsed = Reparsed::Metadata;
+        let (maidata, state) = lex_maidata_with(&source, |difficulty, val| {
+            let old = self.old_section(difficulty);
+            if Some(difficulty) != edited_difficulty {
+                old.restore(&mut val.extra.borrow_mut(), .., &shift);
+                let insns = old.insns.iter().map(|x| shift.insn(x)).collect();
+                return Ok((val_end(val), insns));
+            }
+
+            let (s, (insns, range)) = reparse_notes(val, &old, &shift)?;
+            reparsed = Reparsed::Notes {
+                difficulty,
+                span: Span::from_start_end(val_at(val, range.start), val_at(val, range.end)),
+            };
+            Ok((s, insns))
+        })?;
+
+        *self = Self {
+            source,
+            maidata,
+            state,
+        };
+        Ok(reparsed)
+    }
+
+    /// The span of the metadata value containing the whole edit, if the edit can't change the
+    /// key-value structure.
+    fn edited_value(&self, edit: &TextEdit) -> Option<Span> {
+        let Range { start, end } = edit.range;
+        let removed = &self.source[start..end];
+        // `&` ends a value unless escaped by a backslash
+        if [removed, edit.text.as_str()]
+            .iter()
+            .any(|x| x.contains(['&', '\\']))
+            || self.source[..start].ends_with('\\')
+        {
+            return None;
+        }
+
+        // only the last note string of a difficulty is kept, so the others can't be reused
+        let note_keys = self
+            .maidata
+            .metadata()
+            .iter()
+            .filter_map(|x| note_key_difficulty(x.key()))
+            .collect::<Vec<_>>();
+        if (1..note_keys.len()).any(|i| note_keys[i..].contains(&note_keys[i - 1])) {
+            return None;
+        }
+
+        self.maidata
+            .metadata()
+            .iter()
+            .map(|x| x.value().span())
+            .find(|x| x.byte_offset <= start && end <= x.byte_offset + x.len)
+    }
+
+    fn old_section(&self, difficulty: Difficulty) -> OldSection<'_> {
+        let span = self
+            .maidata
+            .metadata()
+            .iter()
+            .find(|x| note_key_difficulty(x.key()) == Some(difficulty))
+            .map(|x| x.value().span())
+            .unwrap_or(Span::empty_at(0, 1, 1));
+        let insns = self
+            .maidata
+            .difficulties
+            .iter()
+            .find(|x| x.difficulty == difficulty)
+            .map_or(&[][..], |x| &x.insns[..]);
+        let within = |x: Span| {
+            span.byte_offset <= x.byte_offset && x.byte_offset <= span.byte_offset + span.len
+        };
+        OldSection {
+            insns,
+            errors: self
+                .state
+                .errors
+                .iter()
+                .filter(|x| within(x.span()))
+                .collect(),
+            warnings: self
+                .state
+                .warnings
+                .iter()
+                .filter(|x| within(x.span()))
+                .collect(),
+        }
+    }
+}
+
+/// The instructions and messages of a note string before the edit.
+struct OldSection<'a> {
+    insns: &'a [Sp<RawInsn>],
+    errors: Vec<&'a Sp<PError>>,
+    warnings: Vec<&'a Sp<PWarning>>,
+}
+
+impl OldSection<'_> {
+    fn has_message_at(&self, offset: usize) -> bool {
+        self.errors.iter().any(|x| x.span().byte_offset == offset)
+            || self.warnings.iter().any(|x| x.span().byte_offset == offset)
+    }
+
+    /// Add the messages starting in `range` (old offsets) to `state`, moved by `shift`.
+    fn restore<R: std::ops::RangeBounds<usize>>(&self, state: &mut State, range: R, shift: &Shift) {
+        for x in &self.errors {
+            if range.contains(&x.span().byte_offset) {
+                state.add_error((***x).clone(), shift.span(x.span()));
+            }
+        }
+        for x in &self.warnings {
+            if range.contains(&x.span().byte_offset) {
+                state.add_warning((***x).clone(), shift.span(x.span()));
+            }
+        }
+    }
+}
+
+/// Parse the edited part of a note string, reusing the instructions around it. Also returns the
+/// range of bytes that was parsed again.
+fn reparse_notes<'a>(
+    val: NomSpan<'a>,
+    old: &OldSection,
+    shift: &Shift,
+) -> PResult<'a, (Vec<Sp<RawInsn>>, Range<usize>)> {
+    use nom::character::complete::multispace0;
+
+    let val_start = val.location_offset();
+    // parsers look ahead a bit, so start over one instruction early, right where the previous
+    // one ended: unknown characters before the next one may now form a note
+    let mut keep = old
+        .insns
+        .iter()
+        .take_while(|x| x.span().byte_offset + x.span().len <= shift.start)
+        .count()
+        .saturating_sub(1);
+    let restart = |keep: usize| match keep {
+        0 => val_start,
+        _ => old.insns[keep - 1].span().byte_offset + old.insns[keep - 1].span().len,
+    };
+    // a message right at the restart point may come from the previous instruction
+    while keep > 0 && old.has_message_at(restart(keep)) {
+        keep -= 1;
+    }
+    let start = restart(keep);
+
+    let mut insns = old.insns[..keep].to_vec();
+    old.restore(&mut val.extra.borrow_mut(), ..start, shift);
+    let checkpoint = val.extra.borrow().checkpoint();
+    let has_new_message_at = |offset: usize| {
+        let state = val.extra.borrow();
+        state.errors[checkpoint.1..]
+            .iter()
+            .any(|x| x.span().byte_offset == offset)
+            || state.warnings[checkpoint.0..]
+                .iter()
+                .any(|x| x.span().byte_offset == offset)
+    };
+
+    let mut s = val_at(val, start);
+    loop {
+        let (rest, _) = multispace0(s)?;
+        s = rest;
+
+        // from an instruction boundary after the edit on, the old result is still valid
+        let offset = s.location_offset();
+        if let Some(old_offset) = shift.old_offset(offset) {
+            let resync = old
+                .insns
+                .binary_search_by_key(&old_offset, |x| x.span().byte_offset);
+            if let Ok(i) = resync {
+                if !old.has_message_at(old_offset) && !has_new_message_at(offset) {
+                    insns.extend(old.insns[i..].iter().map(|x| shift.insn(x)));
+                    old.restore(&mut val.extra.borrow_mut(), old_offset.., shift);
+                    return Ok((val_end(val), (insns, start..offset)));
+                }
+            }
+        }
+
+        match parse_one_maidata_insn(s) {
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+            Ok((rest, insn)) => {
+                insns.extend(insn);
+                s = rest;
+            }
+        }
+    }
+
+    let end = val_start + val.fragment().len();
+    Ok((s, (insns, start..end)))
+}
+
+fn val_at(val: NomSpan, offset: usize) -> NomSpan {
+    use nom::Slice;
+
+    val.slice(offset - val.location_offset()..)
+}
+
+fn val_end(val: NomSpan) -> NomSpan {
+    use nom::Slice;
+
+    val.slice(val.fragment().len()..)
+}
+
+/// Moves positions after an edit to where they are in the edited text.
+struct Shift {
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+    old_end_line_col: (usize, usize),
+    new_end_line_col: (usize, usize),
+}
+
+impl Shift {
+    fn new(old: &str, new: &str, edit: &TextEdit) -> Self {
+        let new_end = edit.range.start + edit.text.len();
+        Self {
+            start: edit.range.start,
+            old_end: edit.range.end,
+            new_end,
+            old_end_line_col: line_col(old, edit.range.end),
+            new_end_line_col: line_col(new, new_end),
+        }
+    }
+
+    /// The offset before the edit of a position after the edited text.
+    fn old_offset(&self, new_offset: usize) -> Option<usize> {
+        (new_offset >= self.new_end).then(|| new_offset - self.new_end + self.old_end)
+    }
+
+    fn position(&self, offset: usize, line: usize, col: usize) -> (usize, usize, usize) {
+        if offset < self.old_end {
+            return (offset, line, col);
+        }
+
+        let (old_line, old_col) = self.old_end_line_col;
+        let (new_line, new_col) = self.new_end_line_col;
+        let offset = offset - self.old_end + self.new_end;
+        if line == old_line {
+            (offset, new_line, col - old_col + new_col)
+        } else {
+            (offset, line - old_line + new_line, col)
+        }
+    }
+
+    fn span(&self, span: Span) -> Span {
+        let (byte_offset, line, col) = self.position(span.byte_offset, span.line, span.col);
+        let (end, end_line, end_col) =
+            self.position(span.byte_offset + span.len, span.end_line, span.end_col);
+        Span {
+            byte_offset,
+            line,
+            col,
+            end_line,
+            end_col,
+            len: end - byte_offset,
+        }
+    }
+
+    fn insn(&self, insn: &Sp<RawInsn>) -> Sp<RawInsn> {
+        let mut result = insn.clone();
+        *result.span_mut() = self.span(insn.span());
+        let notes: Vec<_> = match &mut *result {
+            RawInsn::Notes(notes) => notes.iter_mut().collect(),
+            RawInsn::PseudoEach(groups) => groups.iter_mut().flatten().collect(),
+            _ => vec![],
+        };
+        for note in notes {
+            *note.span_mut() = self.span(note.span());
+        }
+        result
+    }
+}
+
+/// Line and column of a byte offset, counted like [`Span`]s do.
+fn line_col(x: &str, offset: usize) -> (usize, usize) {
+    let before = &x[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(maidata: &Maidata) -> Vec<Span> {
+        let mut result = vec![];
+        for diff in maidata.iter_difficulties() {
+            for insn in diff.iter_insns() {
+                result.push(insn.span());
+                if let RawInsn::Notes(notes) = &**insn {
+                    result.extend(notes.iter().map(|x| x.span()));
+                }
+            }
+        }
+        result
+    }
+
+    fn messages(state: &State) -> Vec<(String, Span)> {
+        let errors = state.errors.iter().map(|x| (x.to_string(), x.span()));
+        let warnings = state.warnings.iter().map(|x| (x.to_string(), x.span()));
+        errors.chain(warnings).collect()
+    }
+
+    #[test]
+    fn test_incremental() {
+        let source = "&title=a\n&inote_4=(120){4}1,2,\n3,4,\n\n&inote_5=(150){8}\n\
+                      1,2k,3/4,\n5-1[4:1]xx,\n6,7,8,E\n&lv_5=13\n";
+        let mut incremental = IncrementalMaidata::new(source).unwrap();
+        let edits = [
+            // fix `2k` in the middle of the master chart
+            ("2k", 2, "2h[4:1]", true),
+            // add a line before it, moving everything after
+            ("1,2h", 0, "7,\n", true),
+            // break the slide
+            ("5-1", 3, "5-", true),
+            // edit the expert chart, before the master one
+            ("3,4", 1, "1/8", true),
+            // the title, with a line break
+            ("a\n", 1, "b\nc", false),
+            ("&lv_5", 1, "", false),
+        ];
+        for (needle, len, text, notes) in edits {
+            let start = incremental.source().find(needle).unwrap();
+            let edit = TextEdit {
+                range: start..start + len,
+                text: text.to_owned(),
+            };
+            let reparsed = incremental.edit(&edit).unwrap();
+            match reparsed {
+                Reparsed::Notes { span, .. } => {
+                    assert!(notes && span.len < 20, "{:?} parsed again", reparsed)
+                }
+                Reparsed::Metadata | Reparsed::File => assert!(!notes),
+            }
+
+            let (maidata, state) = try_lex_maidata(incremental.source()).unwrap();
+            assert_eq!(*incremental.maidata(), maidata);
+            assert_eq!(spans(incremental.maidata()), spans(&maidata));
+            assert_eq!(messages(incremental.state()), messages(&state));
+        }
+        assert!(incremental
+            .source()
+            .contains("&title=b\nc\n&inote_4=(120){4}1,2,\n1/8,4,"));
+        assert!(incremental.source().ends_with("8,E\nlv_5=13\n"));
+    }
+}
@@ -1,37 +1,232 @@
-use crate::parser::{NomSpan, PResult, State};
+use crate::parser::{NomSpan, PError, PResult, PWarning, Span, State};
+use crate::{Sp, WithSpan};
 use std::collections::HashMap;
 
+mod incremental;
+mod source_map;
+mod writer;
+
+pub use incremental::{IncrementalMaidata, Reparsed, TextEdit};
+pub use source_map::{Location, SourceMap};
+pub use writer::MaidataWriter;
+
 #[derive(Debug)]
 pub(crate) struct KeyVal<'a> {
     pub key: NomSpan<'a>,
     pub val: NomSpan<'a>,
 }
 
-#[derive(Clone, Debug, Default)]
+/// A metadata value, keeping both the text as written in the file and the unescaped form.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct MetadataValue {
+    raw: String,
+    value: String,
+}
+
+impl MetadataValue {
+    /// Create from the text as written in the file, decoding escape sequences.
+    pub fn from_raw<S: Into<String>>(raw: S) -> Self {
+        let raw = raw.into();
+        let value = unescape(&raw);
+        Self { raw, value }
+    }
+
+    /// Create from an unescaped value, escaping as necessary.
+    pub fn from_value<S: Into<String>>(value: S) -> Self {
+        let value = value.into();
+        let raw = escape(&value);
+        Self { raw, value }
+    }
+
+    /// The text as written in the file.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The text with escape sequences decoded.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl std::fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+/// Decode the simai escape sequences `\＆ \＋ \％ \￥` into `& + % \`.
+///
+/// `\&` is also accepted, as an ampersand preceded by a backslash does not start a new key-value
+/// pair. Backslashes not followed by one of these characters are kept verbatim.
+pub fn unescape(x: &str) -> String {
+    let mut result = String::with_capacity(x.len());
+    let mut chars = x.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let unescaped = match chars.peek() {
+                Some('＆' | '&') => Some('&'),
+                Some('＋') => Some('+'),
+                Some('％') => Some('%'),
+                Some('￥') => Some('\\'),
+                _ => None,
+            };
+            if let Some(unescaped) = unescaped {
+                chars.next();
+                result.push(unescaped);
+                continue;
+            }
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Escape a value so that it can be written back as a metadata value, the inverse of
+/// [`unescape`].
+pub fn escape(x: &str) -> String {
+    let mut result = String::with_capacity(x.len());
+    for c in x.chars() {
+        match c {
+            '&' => result.push_str("\\＆"),
+            '\\' => result.push_str("\\￥"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// One `&key=value` pair as it appears in the file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MetadataEntry {
+    key: Sp<String>,
+    value: Sp<MetadataValue>,
+}
+
+impl MetadataEntry {
+    pub fn key(&self) -> &Sp<String> {
+        &self.key
+    }
+
+    pub fn value(&self) -> &Sp<MetadataValue> {
+        &self.value
+    }
+}
+
+/// A message shown in the middle of a chart, as specified by the `amsg_*` fields.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ActiveMessage {
+    /// Position of the message, in measures since the start of the chart.
+    pub measure: f64,
+    /// One item of the comma-separated `amsg_content` list, so it never contains a comma.
+    pub content: MetadataValue,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
 pub struct Maidata {
-    title: String,
-    artist: String,
+    title: Option<Sp<MetadataValue>>,
+    artist: Option<Sp<MetadataValue>>,
 
-    fallback_designer: Option<String>,
+    fallback_designer: Option<Sp<MetadataValue>>,
     fallback_offset: Option<f64>,
-    fallback_single_message: Option<String>,
+    fallback_single_message: Option<Sp<MetadataValue>>,
+    fallback_active_message_offset: Option<f64>,
+    fallback_active_message_times: Option<Vec<Sp<f64>>>,
+    fallback_active_message_contents: Option<Vec<Sp<MetadataValue>>>,
 
     // XXX: is wholebpm mandatory?
-    _star_bpm: Option<f64>,
+    whole_bpm: Option<f64>,
 
     difficulties: Vec<BeatmapData>,
+
+    // every key-value pair in file order, including the ones interpreted above
+    metadata: Vec<MetadataEntry>,
 }
 
 impl Maidata {
     pub fn title(&self) -> &str {
-        &self.title
+        self.title.as_ref().map_or("", |x| x.value())
+    }
+
+    pub fn title_value(&self) -> Option<&Sp<MetadataValue>> {
+        self.title.as_ref()
     }
 
     pub fn artist(&self) -> &str {
-        &self.artist
+        self.artist.as_ref().map_or("", |x| x.value())
+    }
+
+    pub fn artist_value(&self) -> Option<&Sp<MetadataValue>> {
+        self.artist.as_ref()
+    }
+
+    /// The `wholebpm` value, i.e. the BPM shown in song selection.
+    pub fn whole_bpm(&self) -> Option<f64> {
+        self.whole_bpm
+    }
+
+    /// The `seek` value, in seconds.
+    pub fn seek(&self) -> Option<f64> {
+        self.get_parsed("seek")
+    }
+
+    /// The `wait` value, in seconds.
+    pub fn wait(&self) -> Option<f64> {
+        self.get_parsed("wait")
+    }
+
+    /// The `demo_seek` value, in seconds.
+    pub fn demo_seek(&self) -> Option<f64> {
+        self.get_parsed("demo_seek")
+    }
+
+    pub fn track(&self) -> Option<&str> {
+        self.get_str("track")
+    }
+
+    pub fn bg(&self) -> Option<&str> {
+        self.get_str("bg")
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.get_str("genre")
+    }
+
+    pub fn cabinet(&self) -> Option<&str> {
+        self.get_str("cabinet")
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.get_str("version")
+    }
+
+    pub fn chart_converter(&self) -> Option<&str> {
+        self.get_str("ChartConverter")
+    }
+
+    /// All key-value pairs in the order they appear in the file, including unknown ones.
+    pub fn metadata(&self) -> &[MetadataEntry] {
+        &self.metadata
     }
 
-    pub fn iter_difficulties(&self) -> impl Iterator<Item = AssociatedBeatmapData> {
+    /// Look up a value by its unescaped key. The last one wins if the key is duplicated.
+    pub fn get(&self, key: &str) -> Option<&Sp<MetadataValue>> {
+        self.metadata
+            .iter()
+            .rev()
+            .find(|entry| entry.key.as_str() == key)
+            .map(|entry| &entry.value)
+    }
+
+    fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).map(|x| x.value())
+    }
+
+    fn get_parsed<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.get_str(key).and_then(|x| x.trim().parse().ok())
+    }
+
+    pub fn iter_difficulties(&self) -> impl Iterator<Item = AssociatedBeatmapData<'_>> {
         self.difficulties
             .iter()
             .map(move |diff| AssociatedBeatmapData {
@@ -41,14 +236,18 @@ impl Maidata {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct BeatmapData {
     difficulty: crate::Difficulty,
-    designer: Option<String>,
+    designer: Option<Sp<MetadataValue>>,
     offset: Option<f64>,
     level: Option<crate::Level>,
     insns: Vec<crate::Sp<crate::insn::RawInsn>>,
-    single_message: Option<String>,
+    single_message: Option<Sp<MetadataValue>>,
+    active_message_offset: Option<f64>,
+    active_message_times: Option<Vec<Sp<f64>>>,
+    active_message_contents: Option<Vec<Sp<MetadataValue>>>,
+    active_messages: Vec<Sp<ActiveMessage>>,
 }
 
 impl BeatmapData {
@@ -60,6 +259,10 @@ impl BeatmapData {
             level: None,
             insns: vec![],
             single_message: None,
+            active_message_offset: None,
+            active_message_times: None,
+            active_message_contents: None,
+            active_messages: vec![],
         }
     }
 }
@@ -77,14 +280,22 @@ impl<'a> AssociatedBeatmapData<'a> {
     pub fn designer(&self) -> Option<&str> {
         self.map
             .designer
-            .as_deref()
-            .or(self.global.fallback_designer.as_deref())
+            .as_ref()
+            .or(self.global.fallback_designer.as_ref())
+            .map(|x| x.value())
     }
 
     pub fn offset(&self) -> Option<f64> {
         self.map.offset.or(self.global.fallback_offset)
     }
 
+    /// The `wholebpm` of the file, e.g. for [`SlideWaitBpm::Fixed`].
+    ///
+    /// [`SlideWaitBpm::Fixed`]: crate::materialize::SlideWaitBpm::Fixed
+    pub fn whole_bpm(&self) -> Option<f64> {
+        self.global.whole_bpm
+    }
+
     pub fn level(&self) -> Option<crate::Level> {
         self.map.level
     }
@@ -96,8 +307,56 @@ impl<'a> AssociatedBeatmapData<'a> {
     pub fn single_message(&self) -> Option<&str> {
         self.map
             .single_message
-            .as_deref()
-            .or(self.global.fallback_single_message.as_deref())
+            .as_ref()
+            .or(self.global.fallback_single_message.as_ref())
+            .map(|x| x.value())
+    }
+
+    /// Offset in seconds of active messages, used in place of the chart [`offset`](Self::offset).
+    ///
+    /// Given by `amsg_first`.
+    pub fn active_message_offset(&self) -> Option<f64> {
+        self.map
+            .active_message_offset
+            .or(self.global.fallback_active_message_offset)
+    }
+
+    pub fn active_messages(&self) -> &[Sp<ActiveMessage>] {
+        &self.map.active_messages
+    }
+
+    /// Materialize the notes, starting at the [`offset`](Self::offset) if any, and the active
+    /// messages, starting at the [`active_message_offset`](Self::active_message_offset) instead if
+    /// there's one.
+    pub fn materialize(&self) -> crate::materialize::MaterializedChart {
+        self.materialize_with(Default::default())
+    }
+
+    /// [`materialize`](Self::materialize) with non-default options, e.g. to time slide waits by
+    /// the [`whole_bpm`](Self::whole_bpm):
+    ///
+    /// ```
+    /// use maidata::materialize::{MaterializationOptions, SlideWaitBpm};
+    ///
+    /// let (maidata, _) = maidata::container::lex_maidata("&wholebpm=240\n&inote_5=1-5[4:1],\n");
+    /// let chart = maidata.iter_difficulties().next().unwrap();
+    /// let options = MaterializationOptions {
+    ///     slide_wait_bpm: chart.whole_bpm().map_or(SlideWaitBpm::AtSlide, SlideWaitBpm::Fixed),
+    ///     ..Default::default()
+    /// };
+    /// let materialized = chart.materialize_with(options);
+    /// ```
+    pub fn materialize_with(
+        &self,
+        options: crate::materialize::MaterializationOptions,
+    ) -> crate::materialize::MaterializedChart {
+        crate::materialize::MaterializationContext::with_offset(self.offset().unwrap_or(0.0))
+            .options(options)
+            .materialize_chart(
+                self.iter_insns(),
+                self.active_messages(),
+                self.active_message_offset(),
+            )
     }
 }
 
@@ -107,18 +366,126 @@ pub fn parse_maidata_insns(x: &str) -> (Vec<crate::Sp<crate::insn::RawInsn>>, St
     (result, state.into_inner())
 }
 
+/// The difficulty whose note string is stored under `key`, e.g. `inote_5`.
+pub(crate) fn note_key_difficulty(key: &str) -> Option<crate::Difficulty> {
+    use crate::Difficulty::*;
+
+    let difficulty = match key.strip_prefix("inote_")? {
+        "1" => Easy,
+        "2" => Basic,
+        "3" => Advanced,
+        "4" => Expert,
+        "5" => Master,
+        "6" => ReMaster,
+        "7" => Original,
+        _ => return None,
+    };
+    Some(difficulty)
+}
+
+/// Unrecoverable errors encountered while lexing a `maidata.txt` file.
+///
+/// Recoverable problems are reported as [`PError`]s and [`PWarning`]s in the returned [`State`]
+/// instead.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ContainerError {
+    /// The key-value structure of the file could not be lexed.
+    Lex(Span),
+    /// The note data of a difficulty could not be parsed.
+    Insns {
+        difficulty: crate::Difficulty,
+        span: Span,
+    },
+}
+
+impl std::fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerError::Lex(span) => write!(f, "{}failed to lex maidata", span),
+            ContainerError::Insns { difficulty, span } => {
+                write!(f, "{}failed to parse notes of {:?}", span, difficulty)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// Keys that are known to appear in real-world `maidata.txt` files, but are only kept as-is in
+/// [`Maidata::metadata`].
+const KNOWN_AUXILIARY_KEYS: &[&str] = &[
+    "seek",
+    "wait",
+    "track",
+    "bg",
+    "bgm",
+    "movie",
+    "genre",
+    "cabinet",
+    "version",
+    "ChartConverter",
+    "demo_seek",
+    "demo_len",
+];
+
+/// Parse a note string into a lossless [`Cst`](crate::cst::Cst).
+pub fn parse_maidata_cst(x: &str) -> (crate::cst::Cst, State) {
+    let state = std::cell::RefCell::new(State::default());
+    let (_, result) = crate::cst::parse_maidata_cst(NomSpan::new_extra(x, &state)).unwrap();
+    (result, state.into_inner())
+}
+
 pub fn lex_maidata(x: &str) -> (Maidata, State) {
+    try_lex_maidata(x).expect("parse maidata failed")
+}
+
+/// Lex a `maidata.txt` file, reporting unrecoverable problems as a [`ContainerError`] instead of
+/// panicking.
+pub fn try_lex_maidata(x: &str) -> Result<(Maidata, State), ContainerError> {
+    lex_maidata_with(x, |_, val| crate::parser::parse_maidata_insns(val))
+}
+
+/// Lex a `maidata.txt` file, getting the instructions of every `inote_N` value from
+/// `parse_insns`.
+pub(crate) fn lex_maidata_with<F>(
+    x: &str,
+    mut parse_insns: F,
+) -> Result<(Maidata, State), ContainerError>
+where
+    F: for<'a> FnMut(crate::Difficulty, NomSpan<'a>) -> PResult<'a, Vec<Sp<crate::insn::RawInsn>>>,
+{
     let state = std::cell::RefCell::new(State::default());
     let input = NomSpan::new_extra(x, &state);
-    let output = lex_maidata_inner(input);
-
-    let kvs = output.expect("parse maidata failed").1;
+    let kvs = match lex_maidata_inner(input) {
+        Ok((_, kvs)) => kvs,
+        Err(e) => return Err(ContainerError::Lex(nom_err_span(input, e))),
+    };
 
     let mut result = Maidata::default();
     let mut diff_map: HashMap<crate::Difficulty, BeatmapData> = HashMap::new();
+    let mut seen_keys = HashMap::new();
     for kv in kvs {
-        let k = *kv.key.fragment();
+        let key = unescape(kv.key.fragment());
+        let k = key.as_str();
         let v = *kv.val.fragment();
+        let value = || MetadataValue::from_raw(v).with_span(Span::from_fragment(kv.val));
+
+        match seen_keys.get(&key) {
+            Some(&first) => state.borrow_mut().add_warning(
+                PWarning::DuplicateKey {
+                    key: key.clone(),
+                    first,
+                },
+                Span::from_fragment(kv.key),
+            ),
+            None => {
+                seen_keys.insert(key.clone(), Span::from_fragment(kv.key));
+            }
+        }
+        result.metadata.push(MetadataEntry {
+            key: key.clone().with_span(Span::from_fragment(kv.key)),
+            value: value(),
+        });
 
         let mut handled = false;
         // difficulty-specific variables
@@ -129,21 +496,39 @@ pub fn lex_maidata(x: &str) -> (Maidata, State) {
                         let data = diff_map
                             .entry($diff)
                             .or_insert(BeatmapData::default_with_difficulty($diff));
-                        data.designer = Some(v.to_owned());
+                        data.designer = Some(value());
                         handled = true;
                     }
                     concat!("first_", stringify!($num)) => {
                         let data = diff_map
                             .entry($diff)
                             .or_insert(BeatmapData::default_with_difficulty($diff));
-                        data.offset = Some(v.parse().expect("parse offset failed"));
+                        match v.trim().parse::<f64>() {
+                            Ok(offset) if offset.is_finite() => {
+                                data.offset = Some(offset);
+                            }
+                            _ => {
+                                state.borrow_mut().add_error(
+                                    PError::InvalidOffset(v.to_owned()),
+                                    Span::from_fragment(kv.val),
+                                );
+                            }
+                        }
                         handled = true;
                     }
                     concat!("inote_", stringify!($num)) => {
                         let data = diff_map
                             .entry($diff)
                             .or_insert(BeatmapData::default_with_difficulty($diff));
-                        data.insns = crate::parser::parse_maidata_insns(kv.val).unwrap().1;
+                        data.insns = match parse_insns($diff, kv.val) {
+                            Ok((_, insns)) => insns,
+                            Err(e) => {
+                                return Err(ContainerError::Insns {
+                                    difficulty: $diff,
+                                    span: nom_err_span(kv.val, e),
+                                });
+                            }
+                        };
                         handled = true;
                     }
                     concat!("lv_", stringify!($num)) => {
@@ -157,7 +542,10 @@ pub fn lex_maidata(x: &str) -> (Maidata, State) {
                                 data.level = Some(lv);
                             }
                             Err(_) => {
-                                // TODO
+                                state.borrow_mut().add_error(
+                                    PError::InvalidLevel(v.to_owned()),
+                                    Span::from_fragment(kv.val),
+                                );
                             }
                         }
                         handled = true;
@@ -166,7 +554,38 @@ pub fn lex_maidata(x: &str) -> (Maidata, State) {
                         let data = diff_map
                             .entry($diff)
                             .or_insert(BeatmapData::default_with_difficulty($diff));
-                        data.single_message = Some(v.to_owned());
+                        data.single_message = Some(value());
+                        handled = true;
+                    }
+                    concat!("amsg_first_", stringify!($num)) => {
+                        let data = diff_map
+                            .entry($diff)
+                            .or_insert(BeatmapData::default_with_difficulty($diff));
+                        match v.trim().parse::<f64>() {
+                            Ok(offset) if offset.is_finite() => {
+                                data.active_message_offset = Some(offset);
+                            }
+                            _ => {
+                                state.borrow_mut().add_error(
+                                    PError::InvalidOffset(v.to_owned()),
+                                    Span::from_fragment(kv.val),
+                                );
+                            }
+                        }
+                        handled = true;
+                    }
+                    concat!("amsg_time_", stringify!($num)) => {
+                        let data = diff_map
+                            .entry($diff)
+                            .or_insert(BeatmapData::default_with_difficulty($diff));
+                        data.active_message_times = Some(lex_active_message_times(kv.val));
+                        handled = true;
+                    }
+                    concat!("amsg_content_", stringify!($num)) => {
+                        let data = diff_map
+                            .entry($diff)
+                            .or_insert(BeatmapData::default_with_difficulty($diff));
+                        data.active_message_contents = Some(lex_active_message_contents(kv.val));
                         handled = true;
                     }
                     _ => {}
@@ -188,41 +607,172 @@ pub fn lex_maidata(x: &str) -> (Maidata, State) {
         // global variables
         match k {
             "title" => {
-                v.clone_into(&mut result.title);
+                result.title = Some(value());
             }
             "artist" => {
-                v.clone_into(&mut result.artist);
+                result.artist = Some(value());
             }
-            "first" => {
-                match v.parse() {
-                    Ok(offset) => {
-                        result.fallback_offset = Some(offset);
-                    }
-                    Err(_) => {
-                        // TODO
-                    }
+            "first" => match v.trim().parse::<f64>() {
+                Ok(offset) if offset.is_finite() => {
+                    result.fallback_offset = Some(offset);
                 }
-            }
+                _ => {
+                    state.borrow_mut().add_error(
+                        PError::InvalidOffset(v.to_owned()),
+                        Span::from_fragment(kv.val),
+                    );
+                }
+            },
             "des" => {
-                result.fallback_designer = Some(v.to_owned());
+                result.fallback_designer = Some(value());
             }
             "smsg" | "freemsg" => {
-                result.fallback_single_message = Some(v.to_owned());
+                result.fallback_single_message = Some(value());
+            }
+            "amsg_first" => match v.trim().parse::<f64>() {
+                Ok(offset) if offset.is_finite() => {
+                    result.fallback_active_message_offset = Some(offset);
+                }
+                _ => {
+                    state.borrow_mut().add_error(
+                        PError::InvalidOffset(v.to_owned()),
+                        Span::from_fragment(kv.val),
+                    );
+                }
+            },
+            "amsg_time" => {
+                result.fallback_active_message_times = Some(lex_active_message_times(kv.val));
+            }
+            "amsg_content" => {
+                result.fallback_active_message_contents = Some(lex_active_message_contents(kv.val));
+            }
+            "wholebpm" => match v.trim().parse::<f64>() {
+                Ok(bpm) if bpm.is_finite() && bpm > 0.0 => {
+                    result.whole_bpm = Some(bpm);
+                }
+                _ => {
+                    state.borrow_mut().add_error(
+                        PError::InvalidBpm(v.to_owned()),
+                        Span::from_fragment(kv.val),
+                    );
+                }
+            },
+            _ if KNOWN_AUXILIARY_KEYS.contains(&k) => (),
+            _ => {
+                state.borrow_mut().add_warning(
+                    PWarning::UnknownKey(key.clone()),
+                    Span::from_fragment(kv.key),
+                );
             }
-            _ => (),
-            // _ => println!("unimplemented property: {} = {}", k, v),
         }
     }
 
+    // pair up active message timestamps and contents, now that all fallbacks are known
+    for data in diff_map.values_mut() {
+        let times = data
+            .active_message_times
+            .as_ref()
+            .or(result.fallback_active_message_times.as_ref());
+        let contents = data
+            .active_message_contents
+            .as_ref()
+            .or(result.fallback_active_message_contents.as_ref());
+        let (times, contents) = match (times, contents) {
+            (None, None) => continue,
+            (times, contents) => (
+                times.map_or(&[][..], |x| &x[..]),
+                contents.map_or(&[][..], |x| &x[..]),
+            ),
+        };
+        if times.len() != contents.len() {
+            let span = (times.first().map(|x| x.span())).or(contents.first().map(|x| x.span()));
+            if let Some(span) = span {
+                state.borrow_mut().add_warning(
+                    PWarning::ActiveMessageCountMismatch {
+                        times: times.len(),
+                        contents: contents.len(),
+                    },
+                    span,
+                );
+            }
+        }
+        data.active_messages = times
+            .iter()
+            .zip(contents.iter())
+            .map(|(time, content)| {
+                ActiveMessage {
+                    measure: **time,
+                    content: (**content).clone(),
+                }
+                .with_span(content.span())
+            })
+            .collect();
+    }
+
     // put parsed difficulties into result
     result.difficulties.extend(diff_map.into_values());
     result.difficulties.sort_by_key(|x| x.difficulty);
 
-    (result, state.into_inner())
+    Ok((result, state.into_inner()))
+}
+
+/// Split a comma-separated list value into its trimmed items.
+fn split_list(val: NomSpan) -> Vec<NomSpan> {
+    use nom::Slice;
+
+    if val.fragment().trim().is_empty() {
+        return vec![];
+    }
+
+    let mut result = vec![];
+    let mut start = 0;
+    let items = val.fragment().split(',');
+    for item in items {
+        let leading = item.len() - item.trim_start().len();
+        let trailing = num_rightmost_whitespaces(item.trim_start());
+        let end = start + item.len();
+        result.push(val.slice(start + leading..end - trailing));
+        start = end + 1;
+    }
+    result
+}
+
+fn lex_active_message_times(val: NomSpan) -> Vec<Sp<f64>> {
+    split_list(val)
+        .into_iter()
+        .filter_map(|item| match item.fragment().parse::<f64>() {
+            Ok(time) if time.is_finite() && time >= 0.0 => {
+                Some(time.with_span(Span::from_fragment(item)))
+            }
+            _ => {
+                item.extra.borrow_mut().add_error(
+                    PError::InvalidActiveMessageTime(item.fragment().to_string()),
+                    Span::from_fragment(item),
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Commas always separate messages, there is no escape for them, so a message can't contain one.
+fn lex_active_message_contents(val: NomSpan) -> Vec<Sp<MetadataValue>> {
+    split_list(val)
+        .into_iter()
+        .map(|item| MetadataValue::from_raw(*item.fragment()).with_span(Span::from_fragment(item)))
+        .collect()
+}
+
+fn nom_err_span<'a>(input: NomSpan<'a>, e: nom::Err<nom::error::Error<NomSpan<'a>>>) -> Span {
+    match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => Span::from_fragment(e.input),
+        nom::Err::Incomplete(_) => Span::from_fragment(input),
+    }
 }
 
 fn lex_maidata_inner(s: NomSpan) -> PResult<Vec<KeyVal>> {
     use nom::character::complete::char;
+    use nom::character::complete::multispace0;
     use nom::combinator::opt;
     use nom::multi::many0;
 
@@ -233,8 +783,10 @@ fn lex_maidata_inner(s: NomSpan) -> PResult<Vec<KeyVal>> {
     let (s, _) = opt(char('\u{feff}'))(s)?;
 
     let (s, result) = many0(lex_keyval)(s)?;
+    let result = result.into_iter().flatten().collect();
 
     // require EOF
+    let (s, _) = multispace0(s)?;
     let (s, _) = t_eof(s)?;
 
     Ok((s, result))
@@ -246,26 +798,74 @@ fn t_eof(s: NomSpan) -> PResult<NomSpan> {
     eof(s)
 }
 
-fn lex_keyval(s: NomSpan) -> PResult<KeyVal> {
-    use nom::bytes::complete::take_till;
+/// Take characters until one of `stops` is found, skipping those escaped by a backslash.
+fn take_till_unescaped<'a>(
+    stops: &'static [char],
+) -> impl Fn(NomSpan<'a>) -> PResult<'a, NomSpan<'a>> {
+    use nom::InputTake;
+
+    move |s| {
+        let mut prev_backslash = false;
+        let len = s
+            .fragment()
+            .char_indices()
+            .find(|&(_, c)| {
+                let found = !prev_backslash && stops.contains(&c);
+                prev_backslash = c == '\\';
+                found
+            })
+            .map_or(s.fragment().len(), |(i, _)| i);
+        Ok(s.take_split(len))
+    }
+}
+
+fn lex_keyval(s: NomSpan) -> PResult<Option<KeyVal>> {
+    use nom::bytes::complete::take_till1;
     use nom::character::complete::char;
     use nom::character::complete::multispace0;
+    use nom::combinator::opt;
     use nom::Slice;
 
     // we might have whitespaces before the first key-value pair, eat them
     // later pairs have the preceding whitespaces eaten during consumption of the value
     let (s, _) = multispace0(s)?;
 
-    let (s, _) = char('&')(s)?;
-    let (s, key) = take_till(|x| x == '=')(s)?;
-    let (s, _) = char('=')(s)?;
-    let (s, val) = take_till(|x| x == '&')(s)?;
+    let (s, amp) = opt(char('&'))(s)?;
+    if amp.is_none() {
+        // stray text not belonging to any key-value pair, skip until the next one
+        let (s, text) = take_till1(|x| x == '&')(s)?;
+        let text =
+            text.slice(0..text.fragment().len() - num_rightmost_whitespaces(text.fragment()));
+        s.extra.borrow_mut().add_error(
+            PError::MissingBefore {
+                missing: "`&`".to_string(),
+                next: "key".to_string(),
+            },
+            Span::from_fragment(text),
+        );
+        return Ok((s, None));
+    }
+
+    let (s, key) = take_till_unescaped(&['=', '&'])(s)?;
+    let (s, eq) = opt(char('='))(s)?;
+    if eq.is_none() {
+        let key = key.slice(0..key.fragment().len() - num_rightmost_whitespaces(key.fragment()));
+        s.extra.borrow_mut().add_error(
+            PError::MissingAfter {
+                missing: "`=`".to_string(),
+                previous: "key".to_string(),
+            },
+            Span::from_fragment(key),
+        );
+        return Ok((s, None));
+    }
+    let (s, val) = take_till_unescaped(&['&'])(s)?;
 
     // strip off trailing newlines from value
     let num_bytes_to_remove = num_rightmost_whitespaces(val.fragment());
     let val = val.slice(0..val.fragment().len() - num_bytes_to_remove);
 
-    Ok((s, KeyVal { key, val }))
+    Ok((s, Some(KeyVal { key, val })))
 }
 
 fn num_rightmost_whitespaces<S: AsRef<str>>(x: S) -> usize {
@@ -300,24 +900,40 @@ fn t_level(s: NomSpan) -> PResult<crate::Level> {
 }
 
 fn t_level_num(s: NomSpan) -> PResult<crate::Level> {
+    use nom::branch::alt;
     use nom::character::complete::char;
     use nom::character::complete::digit1;
     use nom::character::complete::multispace0;
-    use nom::combinator::opt;
+    use nom::character::complete::satisfy;
+    use nom::combinator::{map, opt};
+    use nom::sequence::preceded;
 
     let (s, num) = digit1(s)?;
-    let (s, _) = multispace0(s)?;
-    let (s, plus) = opt(char('+'))(s)?;
+    // either `+` or a single decimal digit, e.g. `13+` or `13.7`
+    let (s, suffix) = opt(alt((
+        map(preceded(multispace0, char('+')), |_| None),
+        map(preceded(char('.'), satisfy(|c| c.is_ascii_digit())), Some),
+    )))(s)?;
     let (s, _) = multispace0(s)?;
 
-    let lv = num.fragment().parse().unwrap();
+    let lv: u8 = match num.fragment().parse() {
+        Ok(lv) => lv,
+        Err(_) => {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                num,
+                nom::error::ErrorKind::Digit,
+            )))
+        }
+    };
 
     Ok((
         s,
-        if plus.is_some() {
-            crate::Level::Plus(lv)
-        } else {
-            crate::Level::Normal(lv)
+        match suffix {
+            None => crate::Level::Normal(lv),
+            Some(None) => crate::Level::Plus(lv),
+            Some(Some(tenths)) => {
+                crate::Level::Decimal(lv as u16 * 10 + tenths.to_digit(10).unwrap() as u16)
+            }
         },
     ))
 }
@@ -339,7 +955,8 @@ impl std::convert::TryFrom<NomSpan<'_>> for crate::Level {
 
     fn try_from(value: NomSpan) -> Result<Self, Self::Error> {
         match t_level(value) {
-            Ok((_, value)) => Ok(value),
+            Ok((rest, value)) if rest.fragment().is_empty() => Ok(value),
+            Ok((rest, _)) => Err(format!("trailing characters `{}`", rest.fragment())),
             Err(e) => Err(format!("{:?}", e)),
         }
     }
@@ -358,4 +975,331 @@ mod tests {
         assert_eq!(num_rightmost_whitespaces("foo\r\n\r\nbar"), 0);
         assert_eq!(num_rightmost_whitespaces("\n\n\nfoo\n\nbar\n"), 1);
     }
+
+    #[test]
+    fn test_try_lex_maidata() {
+        use super::try_lex_maidata;
+        use crate::{PError, PWarning};
+
+        let (maidata, state) =
+            try_lex_maidata("&title=foo\n&first=0.5\n&lv_5=13+\n&inote_5=1,\n").unwrap();
+        assert!(!state.has_messages());
+        assert_eq!(maidata.title(), "foo");
+        let diff = maidata.iter_difficulties().next().unwrap();
+        assert_eq!(diff.offset(), Some(0.5));
+        assert_eq!(diff.level(), Some(crate::Level::Plus(13)));
+
+        let (maidata, state) = try_lex_maidata(
+            "garbage\n&first_5=abc\n&lv_5=999\n&foo=bar\n&title=a\n&title=b\n&artist\n",
+        )
+        .unwrap();
+        assert_eq!(maidata.title(), "b");
+        let diff = maidata.iter_difficulties().next().unwrap();
+        assert_eq!(diff.offset(), None);
+        assert_eq!(diff.level(), None);
+        let errors = state
+            .errors
+            .iter()
+            .map(|x| (**x).clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                PError::MissingBefore {
+                    missing: "`&`".to_string(),
+                    next: "key".to_string(),
+                },
+                PError::MissingAfter {
+                    missing: "`=`".to_string(),
+                    previous: "key".to_string(),
+                },
+                PError::InvalidOffset("abc".to_string()),
+                PError::InvalidLevel("999".to_string()),
+            ]
+        );
+        assert_eq!(state.errors[0].span().len, "garbage".len());
+        let warnings = state
+            .warnings
+            .iter()
+            .map(|x| (**x).clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                PWarning::UnknownKey("foo".to_string()),
+                PWarning::DuplicateKey {
+                    key: "title".to_string(),
+                    first: crate::Span {
+                        byte_offset: 41,
+                        line: 5,
+                        col: 2,
+                        end_line: 5,
+                        end_col: 7,
+                        len: 5,
+                    },
+                },
+            ]
+        );
+
+        assert!(try_lex_maidata("").is_ok());
+        assert!(try_lex_maidata("  \n").is_ok());
+
+        // numbers out of range in note strings are errors too, not panics
+        let (_, state) = try_lex_maidata("&inote_5=(120){4}1h[99999999999:1],\n").unwrap();
+        let errors = state
+            .errors
+            .iter()
+            .map(|x| (**x).clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![PError::InvalidDuration("99999999999:1".to_string())]
+        );
+        assert_eq!(state.errors[0].span().len, "99999999999:1".len());
+
+        let (maidata, state) = try_lex_maidata("&inote_5={99999999999}1,\n").unwrap();
+        assert_eq!(
+            *state.errors[0],
+            PError::InvalidBeatDivisor("99999999999".to_string())
+        );
+        assert_eq!(state.errors[0].span().len, "99999999999".len());
+        let insns = maidata.iter_difficulties().next().unwrap();
+        assert_eq!(insns.iter_insns().count(), 1);
+
+        // offsets must be finite
+        let (maidata, state) =
+            try_lex_maidata("&first=1e400\n&first_5=nan\n&amsg_first=-inf\n&inote_5=1,\n").unwrap();
+        let errors = state
+            .errors
+            .iter()
+            .map(|x| (**x).clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                PError::InvalidOffset("1e400".to_string()),
+                PError::InvalidOffset("nan".to_string()),
+                PError::InvalidOffset("-inf".to_string()),
+            ]
+        );
+        let chart = maidata.iter_difficulties().next().unwrap();
+        assert_eq!(chart.offset(), None);
+        assert_eq!(chart.active_message_offset(), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        use super::{escape, try_lex_maidata, unescape};
+
+        assert_eq!(unescape("a\\＆b\\＋c\\％d\\￥e"), "a&b+c%d\\e");
+        assert_eq!(unescape("a\\&b"), "a&b");
+        assert_eq!(unescape("a\\b＆"), "a\\b＆");
+        assert_eq!(escape("a&b\\c+"), "a\\＆b\\￥c+");
+        assert_eq!(unescape(&escape("a&b\\＆c")), "a&b\\＆c");
+
+        let (maidata, state) = try_lex_maidata("&title=Foo \\& Bar\n&artist=A\\＆B\n").unwrap();
+        assert!(!state.has_messages());
+        assert_eq!(maidata.title(), "Foo & Bar");
+        assert_eq!(maidata.artist(), "A&B");
+        let artist = maidata.artist_value().unwrap();
+        assert_eq!(artist.raw(), "A\\＆B");
+        assert_eq!(artist.span().col, 9);
+    }
+
+    #[test]
+    fn test_metadata() {
+        use super::try_lex_maidata;
+
+        let (maidata, state) = try_lex_maidata(
+            "&title=foo\n&wholebpm=180\n&seek=12.5\n&genre=maimai\n&ChartConverter=x\n\
+             &custom=1\n&custom=2\n",
+        )
+        .unwrap();
+        assert_eq!(state.warnings.len(), 3);
+        assert_eq!(maidata.whole_bpm(), Some(180.0));
+        assert_eq!(maidata.seek(), Some(12.5));
+        assert_eq!(maidata.wait(), None);
+        assert_eq!(maidata.genre(), Some("maimai"));
+        assert_eq!(maidata.chart_converter(), Some("x"));
+        assert_eq!(maidata.get("custom").unwrap().value(), "2");
+        let keys = maidata
+            .metadata()
+            .iter()
+            .map(|x| x.key().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "title",
+                "wholebpm",
+                "seek",
+                "genre",
+                "ChartConverter",
+                "custom",
+                "custom"
+            ]
+        );
+        assert_eq!(maidata.metadata()[1].value().span().line, 2);
+
+        let (maidata, state) = try_lex_maidata("&wholebpm=abc\n").unwrap();
+        assert!(state.has_errors());
+        assert_eq!(maidata.whole_bpm(), None);
+    }
+
+    #[test]
+    fn test_materialize_with() {
+        use super::lex_maidata;
+        use crate::materialize::{MaterializationOptions, Note, SlideWaitBpm};
+
+        let (maidata, _) = lex_maidata("&wholebpm=240\n&inote_5=(120){4}1,(60)1-5[4:1],\n");
+        let chart = maidata.iter_difficulties().next().unwrap();
+        assert_eq!(chart.whole_bpm(), Some(240.0));
+        let slide_start_ts = |chart: crate::materialize::MaterializedChart| {
+            chart
+                .notes
+                .iter()
+                .find_map(|x| match &**x {
+                    Note::SlideTrack(track) => Some(track.start_ts),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // one beat at BPM 60, where the slide is
+        assert_eq!(slide_start_ts(chart.materialize()), 1.5);
+        // one beat at the `wholebpm` of 240
+        let options = MaterializationOptions {
+            slide_wait_bpm: SlideWaitBpm::Fixed(chart.whole_bpm().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(slide_start_ts(chart.materialize_with(options)), 0.75);
+    }
+
+    #[test]
+    fn test_active_messages() {
+        use super::try_lex_maidata;
+        use crate::materialize::MaterializationContext;
+
+        let (maidata, state) = try_lex_maidata(
+            "&amsg_first=0.5\n&amsg_time=1, 2.5\n&amsg_content=hello, world\n\
+             &inote_5=(120){1},(240),,\n&amsg_time_6=1\n&inote_6=1,\n",
+        )
+        .unwrap();
+        assert!(!state.has_errors());
+        // the Re:MASTER one has 1 timestamp but 2 contents
+        assert_eq!(state.warnings.len(), 1);
+        let diffs = maidata.iter_difficulties().collect::<Vec<_>>();
+        let master = &diffs[0];
+        assert_eq!(master.active_message_offset(), Some(0.5));
+        let messages = master.active_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].measure, 1.0);
+        assert_eq!(messages[1].content.value(), "world");
+        assert_eq!(messages[1].span().line, 3);
+        assert_eq!(messages[1].span().col, 22);
+
+        let chart = master.materialize();
+        let messages = &chart.active_messages;
+        // 1 measure at BPM 120 is 2s, then 1.5 measures at BPM 240 is 1.5s
+        assert_eq!(messages[0].ts, 2.5);
+        assert_eq!(messages[1].ts, 4.0);
+        assert_eq!(messages[1].content, "world");
+
+        // `amsg_first` replaces the chart offset `first` for messages, it doesn't add to it
+        let (maidata, _) = try_lex_maidata(
+            "&first=1\n&amsg_first=0.25\n&amsg_time=1\n&amsg_content=a\n&inote_5=(120){1},,\n",
+        )
+        .unwrap();
+        let chart = maidata.iter_difficulties().next().unwrap().materialize();
+        assert_eq!(chart.timing.ts_at_measure(1.0), 3.0);
+        assert_eq!(chart.active_messages[0].ts, 2.25);
+        let (maidata, _) =
+            try_lex_maidata("&first=1\n&amsg_time=1\n&amsg_content=a\n&inote_5=(120){1},,\n")
+                .unwrap();
+        let chart = maidata.iter_difficulties().next().unwrap().materialize();
+        assert_eq!(chart.active_messages[0].ts, 3.0);
+
+        let mut mcx = MaterializationContext::with_offset(1.0);
+        let chart = mcx.materialize_chart(master.iter_insns(), master.active_messages(), None);
+        assert_eq!(chart.active_messages[1].ts, 4.5);
+
+        // times overridden per difficulty, contents falling back to the global ones
+        assert_eq!(diffs[1].active_messages().len(), 1);
+
+        let (_, state) = try_lex_maidata("&amsg_time=1,x\n&amsg_content=a,b\n&inote_1=1,").unwrap();
+        assert_eq!(state.errors.len(), 1);
+        assert_eq!(state.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_level() {
+        use super::try_lex_maidata;
+        use crate::{Level, PError};
+
+        let level = |x: &str| {
+            let (maidata, state) = try_lex_maidata(&format!("&lv_5={}\n", x)).unwrap();
+            let level = maidata.iter_difficulties().next().unwrap().level();
+            (level, state.errors.first().map(|x| (**x).clone()))
+        };
+        assert_eq!(level("13.7"), (Some(Level::Decimal(137)), None));
+        assert_eq!(level("13 +"), (Some(Level::Plus(13)), None));
+        assert_eq!(Level::Decimal(137).to_string(), "13.7");
+        assert_eq!(Level::Decimal(140).to_string(), "14.0");
+        assert_eq!(Level::Decimal(137).constant(), Some(13.7));
+        for x in ["13.75", "13.7+", "13.", ".7"] {
+            assert_eq!(
+                level(x),
+                (None, Some(PError::InvalidLevel(x.to_string()))),
+                "{}",
+                x
+            );
+        }
+
+        let mut levels = [
+            Level::Char('?'),
+            Level::Normal(14),
+            Level::Decimal(137),
+            Level::Plus(13),
+            Level::Decimal(136),
+            Level::Decimal(130),
+            Level::Normal(13),
+        ];
+        levels.sort();
+        assert_eq!(
+            levels.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+            vec!["13", "13.0", "13.6", "13+", "13.7", "14", "?"]
+        );
+    }
+
+    #[test]
+    fn test_materialize() {
+        use super::try_lex_maidata;
+        use crate::materialize::Note;
+
+        let (maidata, _) = try_lex_maidata(
+            "&first=1\n&inote_5=(120){4}1h[4:1],2/3,(60)4-6[2:1],E,5,\n&first_6=0.5\n&inote_6=(120){4}1,,\n",
+        )
+        .unwrap();
+        let diffs = maidata.iter_difficulties().collect::<Vec<_>>();
+
+        let chart = diffs[0].materialize();
+        assert_eq!(chart.bpm_events.len(), 2);
+        assert_eq!(chart.bpm_events[1].ts, 2.0);
+        // sorted by timestamp, and nothing after `E`
+        let notes = chart
+            .notes
+            .iter()
+            .map(|x| (x.ts(), matches!(**x, Note::SlideTrack(_))))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            notes,
+            vec![(1.0, false), (1.5, false), (1.5, false), (2.0, true)]
+        );
+        assert_eq!(chart.end_ts, 3.0);
+
+        // without an end mark, the chart ends after the last rest
+        let chart = diffs[1].materialize();
+        assert_eq!(chart.notes[0].ts(), 0.5);
+        assert_eq!(chart.end_ts, 1.5);
+    }
 }
@@ -0,0 +1,345 @@
+//! Mapping between byte offsets, whole-file spans and note-string-relative spans.
+
+use super::{note_key_difficulty, Maidata};
+use crate::insn::{BeatDivisorParams, RawInsn};
+use crate::materialize::Rational;
+use crate::{Difficulty, Span};
+
+/// Line and column lookup for a `maidata.txt` file, and the positions of its note strings.
+///
+/// Spans inside a [`Maidata`] are relative to the whole file, while the spans returned by
+/// [`parse_maidata_insns`](super::parse_maidata_insns) start over at line 1 of the note string.
+/// The map converts between the two, and tells which difficulty and measure a span is in.
+#[derive(Clone, Debug)]
+pub struct SourceMap<'a> {
+    source: &'a str,
+    /// Byte offset of the start of every line.
+    line_starts: Vec<usize>,
+    sections: Vec<Section>,
+}
+
+/// The value of an `inote_N` entry.
+#[derive(Clone, Debug)]
+struct Section {
+    difficulty: Difficulty,
+    span: Span,
+    insns: Vec<InsnLocation>,
+}
+
+#[derive(Clone, Debug)]
+struct InsnLocation {
+    span: Span,
+    measure: usize,
+    /// Span and number in the measure of every note of the instruction.
+    notes: Vec<(Span, usize)>,
+}
+
+/// Where a span is in a chart, e.g. "Master, measure 37, note 2".
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Location {
+    pub difficulty: Difficulty,
+    /// Measure number, starting from 1. Measures are counted in 4/4 time like
+    /// [`MusicalPosition::measure`](crate::materialize::MusicalPosition::measure), so notes of
+    /// `{#x}` divisors don't advance them.
+    pub measure: usize,
+    /// Number of the note among the notes of the measure in source order, starting from 1.
+    /// `None` for anything but notes, e.g. BPM changes and rests.
+    pub note: Option<usize>,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}, measure {}", self.difficulty, self.measure)?;
+        if let Some(note) = self.note {
+            write!(f, ", note {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SourceMap<'a> {
+    /// `source` must be the text `maidata` was lexed from.
+    pub fn new(source: &'a str, maidata: &Maidata) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        // like the difficulties themselves, the last entry of a key wins
+        let mut sections: Vec<Section> = vec![];
+        for entry in maidata.metadata() {
+            let difficulty = match note_key_difficulty(entry.key()) {
+                Some(x) => x,
+                None => continue,
+            };
+            sections.retain(|x| x.difficulty != difficulty);
+            let insns = maidata
+                .difficulties
+                .iter()
+                .find(|x| x.difficulty == difficulty)
+                .map_or(&[][..], |x| &x.insns[..]);
+            sections.push(Section {
+                difficulty,
+                span: entry.value().span(),
+                insns: locate_insns(insns),
+            });
+        }
+
+        Self {
+            source,
+            line_starts,
+            sections,
+        }
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// Line and column of a byte offset, both starting from 1. Columns count UTF-8 characters
+    /// like [`Span::col`]. Offsets past the end are clamped.
+    pub fn line_col(&self, byte_offset: usize) -> (usize, usize) {
+        let byte_offset = byte_offset.min(self.source.len());
+        let line = self.line_starts.partition_point(|&x| x <= byte_offset);
+        let line_start = self.line_starts[line - 1];
+        let col = self.source[line_start..byte_offset].chars().count() + 1;
+        (line, col)
+    }
+
+    /// The whole-file span of `len` bytes starting at `byte_offset`.
+    pub fn span(&self, byte_offset: usize, len: usize) -> Span {
+        let (line, col) = self.line_col(byte_offset);
+        let (end_line, end_col) = self.line_col(byte_offset + len);
+        Span {
+            byte_offset,
+            line,
+            col,
+            end_line,
+            end_col,
+            len,
+        }
+    }
+
+    /// The whole-file span of the note string of `difficulty`.
+    pub fn note_string_span(&self, difficulty: Difficulty) -> Option<Span> {
+        self.section(difficulty).map(|x| x.span)
+    }
+
+    /// Convert a span relative to the note string of `difficulty`, as returned by
+    /// [`parse_maidata_insns`](super::parse_maidata_insns), into a whole-file span.
+    pub fn to_file_span(&self, difficulty: Difficulty, span: Span) -> Option<Span> {
+        let base = self.section(difficulty)?.span;
+        Some(span.offset_by(base))
+    }
+
+    /// Convert a whole-file span into one relative to the note string containing its start,
+    /// the inverse of [`to_file_span`](Self::to_file_span).
+    pub fn to_note_span(&self, span: Span) -> Option<(Difficulty, Span)> {
+        let section = self.section_at(span.byte_offset)?;
+        let base = section.span;
+        let (line, col) = shift_back(base, span.line, span.col);
+        let (end_line, end_col) = shift_back(base, span.end_line, span.end_col);
+        let span = Span {
+            byte_offset: span.byte_offset - base.byte_offset,
+            line,
+            col,
+            end_line,
+            end_col,
+            len: span.len,
+        };
+        Some((section.difficulty, span))
+    }
+
+    /// The difficulty, measure and note a whole-file span starts in. `None` if it is outside
+    /// all note strings, or between instructions.
+    pub fn locate(&self, span: Span) -> Option<Location> {
+        let offset = span.byte_offset;
+        let section = self.section_at(offset)?;
+        let i = section
+            .insns
+            .partition_point(|x| x.span.byte_offset <= offset)
+            .checked_sub(1)?;
+        let insn = &section.insns[i];
+        if offset >= insn.span.byte_offset + insn.span.len.max(1) {
+            return None;
+        }
+        // separators belong to the first note of the bundle
+        let note = insn
+            .notes
+            .iter()
+            .rev()
+            .find(|(x, _)| x.byte_offset <= offset)
+            .or_else(|| insn.notes.first())
+            .map(|(_, num)| *num);
+        Some(Location {
+            difficulty: section.difficulty,
+            measure: insn.measure,
+            note,
+        })
+    }
+
+    fn section(&self, difficulty: Difficulty) -> Option<&Section> {
+        self.sections.iter().find(|x| x.difficulty == difficulty)
+    }
+
+    fn section_at(&self, byte_offset: usize) -> Option<&Section> {
+        self.sections.iter().find(|x| {
+            x.span.byte_offset <= byte_offset && byte_offset <= x.span.byte_offset + x.span.len
+        })
+    }
+}
+
+fn shift_back(base: Span, line: usize, col: usize) -> (usize, usize) {
+    if line <= base.line {
+        (1, col + 1 - base.col)
+    } else {
+        (line + 1 - base.line, col)
+    }
+}
+
+fn locate_insns(insns: &[crate::Sp<RawInsn>]) -> Vec<InsnLocation> {
+    // length of one note in measures, `None` for `{#x}`
+    let mut note_len = Some(Rational::new(1, 4));
+    // position in measures since the start, `None` once it is too fine to be kept exact
+    let mut pos = Some(Rational::ZERO);
+    let mut last_measure = 0;
+    let mut notes_in_measure = 0;
+    let mut result = Vec::with_capacity(insns.len());
+    for insn in insns {
+        // the last known measure goes on
+        let measure = pos.map_or(last_measure, |pos| pos.floor() as usize + 1);
+        if measure != last_measure {
+            last_measure = measure;
+            notes_in_measure = 0;
+        }
+
+        let notes = match &**insn {
+            RawInsn::Notes(notes) => notes.iter().collect(),
+            RawInsn::PseudoEach(groups) => groups.iter().flatten().collect(),
+            _ => vec![],
+        };
+        let notes = notes
+            .into_iter()
+            .map(|x| {
+                notes_in_measure += 1;
+                (x.span(), notes_in_measure)
+            })
+            .collect();
+        result.push(InsnLocation {
+            span: insn.span(),
+            measure,
+            notes,
+        });
+
+        match &**insn {
+            RawInsn::BeatDivisor(BeatDivisorParams::NewDivisor(x)) => {
+                // the parser rejects `{0}`
+                note_len = Some(Rational::new(1, (*x).max(1) as i64));
+            }
+            RawInsn::BeatDivisor(BeatDivisorParams::NewAbsoluteDuration(_)) => note_len = None,
+            RawInsn::Rest | RawInsn::Notes(_) | RawInsn::PseudoEach(_) => {
+                if let Some(x) = note_len {
+                    pos = pos.and_then(|pos| pos.checked_add(x));
+                }
+            }
+            RawInsn::Bpm(_) | RawInsn::EndMark => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{lex_maidata, parse_maidata_insns};
+
+    #[test]
+    fn test_source_map() {
+        let source = "&title=x\n&inote_5=(120){4}1,2/3,\n  4,5,\n{2}6,,E\n&lv_5=13\n";
+        let (maidata, _) = lex_maidata(source);
+        let map = SourceMap::new(source, &maidata);
+
+        assert_eq!(map.line_col(0), (1, 1));
+        assert_eq!(map.line_col(9), (2, 1));
+        assert_eq!(map.line_col(source.len()), (6, 1));
+
+        let insns = maidata
+            .iter_difficulties()
+            .next()
+            .unwrap()
+            .iter_insns()
+            .cloned()
+            .collect::<Vec<_>>();
+        let locations = insns
+            .iter()
+            .map(|x| map.locate(x.span()).map(|x| x.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            vec![
+                Some("Master, measure 1".to_owned()),
+                Some("Master, measure 1".to_owned()),
+                Some("Master, measure 1, note 1".to_owned()),
+                Some("Master, measure 1, note 2".to_owned()),
+                Some("Master, measure 1, note 4".to_owned()),
+                Some("Master, measure 1, note 5".to_owned()),
+                Some("Master, measure 2".to_owned()),
+                Some("Master, measure 2, note 1".to_owned()),
+                Some("Master, measure 2".to_owned()),
+                Some("Master, measure 3".to_owned()),
+            ]
+        );
+        // the second note of `2/3,`
+        let RawInsn::Notes(notes) = &*insns[3] else {
+            unreachable!()
+        };
+        assert_eq!(map.locate(notes[1].span()).unwrap().note, Some(3));
+        assert_eq!(map.locate(map.span(1, 1)), None);
+
+        // relative spans map to the same text as the ones from the whole file
+        let note_string = maidata.get("inote_5").unwrap().raw().to_owned();
+        let (relative, _) = parse_maidata_insns(&note_string);
+        for (rel, abs) in relative.iter().zip(insns.iter()) {
+            assert_eq!(
+                map.to_file_span(Difficulty::Master, rel.span()),
+                Some(abs.span())
+            );
+            assert_eq!(
+                map.to_note_span(abs.span()),
+                Some((Difficulty::Master, rel.span()))
+            );
+        }
+        assert_eq!(
+            map.to_file_span(Difficulty::Basic, relative[0].span()),
+            None
+        );
+        let span = insns[4].span();
+        assert_eq!(map.span(span.byte_offset, span.len), span);
+    }
+
+    #[test]
+    fn test_locate_insns() {
+        let measures = |x: &str| {
+            let (insns, _) = parse_maidata_insns(x);
+            locate_insns(&insns)
+                .into_iter()
+                .map(|x| x.measure)
+                .collect::<Vec<_>>()
+        };
+
+        // no rounding error from thirds and sixths
+        assert_eq!(
+            measures("{3}1,2,3,{6}4,5,6,7,8,1,2,"),
+            vec![1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 3]
+        );
+        // `{#x}` notes take no measures, whatever the BPM
+        assert_eq!(
+            measures("(120){2}1,{#0.5}2,3,{2}4,5,"),
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 2]
+        );
+        // positions too fine to be kept exact stay in the last known measure
+        assert_eq!(
+            measures("(120){999983}1,{999979}1,{999961}1,{999959}1,{999953}1,{1}1,1,"),
+            vec![1; 14]
+        );
+    }
+}
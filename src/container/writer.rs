@@ -0,0 +1,186 @@
+//! Canonical `maidata.txt` output.
+
+use super::{escape, note_key_difficulty, Maidata};
+use crate::insn::{BeatDivisorParams, PrintError, RawInsn};
+use crate::materialize::Rational;
+use crate::{Sp, WithSpan};
+use std::fmt::Write;
+
+/// Writes a [`Maidata`] back out as `maidata.txt` text.
+///
+/// Metadata entries are written in their original order with their values as written in the
+/// source file. Note strings are regenerated from the parsed instructions instead, so the output
+/// is canonical: one measure per line, no comments or extra whitespace.
+///
+/// Lexing the output gives back a [`Maidata`] equal to the input. Instructions without maidata
+/// syntax, see [`RawInsn::check_printable`], are reported as errors instead of being written.
+#[derive(Copy, Clone, Debug)]
+pub struct MaidataWriter {
+    wrap_measures: bool,
+}
+
+impl Default for MaidataWriter {
+    fn default() -> Self {
+        Self {
+            wrap_measures: true,
+        }
+    }
+}
+
+impl MaidataWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to start a new line after every measure of notes. Defaults to `true`.
+    pub fn wrap_measures(mut self, wrap_measures: bool) -> Self {
+        self.wrap_measures = wrap_measures;
+        self
+    }
+
+    /// Write a whole file, failing on the first instruction that has no maidata syntax.
+    pub fn write(&self, maidata: &Maidata) -> Result<String, Sp<PrintError>> {
+        let mut result = String::new();
+        for entry in maidata.metadata() {
+            write!(result, "&{}=", escape(entry.key())).unwrap();
+            match note_key_difficulty(entry.key()) {
+                Some(difficulty) => {
+                    let insns = maidata
+                        .difficulties
+                        .iter()
+                        .find(|x| x.difficulty == difficulty)
+                        .map_or(&[][..], |x| &x.insns[..]);
+                    self.write_insns_to(&mut result, insns)?;
+                }
+                None => writeln!(result, "{}", entry.value().raw()).unwrap(),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Write a note string, e.g. the value of `inote_N`.
+    pub fn write_insns(&self, insns: &[Sp<RawInsn>]) -> Result<String, Sp<PrintError>> {
+        let mut result = String::new();
+        self.write_insns_to(&mut result, insns)?;
+        Ok(result)
+    }
+
+    fn write_insns_to(&self, w: &mut String, insns: &[Sp<RawInsn>]) -> Result<(), Sp<PrintError>> {
+        // length of one note in measures, `None` with absolute durations
+        let mut note_len = Some(Rational::new(1, 4));
+        // position in measures since the start, `None` once it is too fine to be kept exact
+        let mut pos = Some(Rational::ZERO);
+        let mut at_line_start = true;
+        for insn in insns {
+            insn.check_printable()
+                .map_err(|e| e.with_span(insn.span()))?;
+            write!(w, "{}", **insn).unwrap();
+            at_line_start = false;
+            match &**insn {
+                RawInsn::BeatDivisor(BeatDivisorParams::NewDivisor(x)) => {
+                    // rejected by `check_printable` above
+                    note_len = Some(Rational::new(1, *x as i64));
+                }
+                RawInsn::BeatDivisor(BeatDivisorParams::NewAbsoluteDuration(_)) => {
+                    note_len = None;
+                }
+                RawInsn::Rest | RawInsn::Notes(_) | RawInsn::PseudoEach(_) => {
+                    if let Some(x) = note_len {
+                        pos = pos.and_then(|pos| pos.checked_add(x));
+                        if self.wrap_measures && pos.is_some_and(|pos| pos.den() == 1) {
+                            w.push('\n');
+                            at_line_start = true;
+                        }
+                    }
+                }
+                RawInsn::EndMark => {
+                    w.push('\n');
+                    at_line_start = true;
+                }
+                RawInsn::Bpm(_) => {}
+            }
+        }
+        if !at_line_start || insns.is_empty() {
+            w.push('\n');
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::lex_maidata;
+    use crate::insn::*;
+
+    #[test]
+    fn test_write_insns() {
+        let (insns, _) = crate::container::parse_maidata_insns(
+            "(120){4}1,2 ,3/4,, ||comment\n{2}1$,1@-5[4:1]b,{#0.5}2`3,,E",
+        );
+        assert_eq!(
+            MaidataWriter::new().write_insns(&insns).unwrap(),
+            "(120){4}1,2,3/4,,\n{2}1$,1@-5[4:1]b,\n{#0.5}2`3,,E\n"
+        );
+
+        let span = insns[2].span();
+        let empty = [RawInsn::Notes(vec![]).with_span(span)];
+        let err = MaidataWriter::new().write_insns(&empty).unwrap_err();
+        assert_eq!(*err, PrintError::EmptyBundle);
+        assert_eq!(err.span(), span);
+
+        // not expressible as `[160#4:1]`, which has a different meaning
+        let dur = SlideDuration::Custom(
+            SlideStopTimeSpec::Bpm(160.0),
+            Duration::NumBeats(NumBeatsParams {
+                bpm: None,
+                divisor: 4,
+                num: Rational::from(1),
+            }),
+        );
+        assert_eq!(dur.check_printable(), Err(PrintError::BpmStopTimeWithBeats));
+        let (mut insns, _) = crate::container::parse_maidata_insns("1-5[4:1],");
+        if let RawInsn::Notes(notes) = &mut *insns[0] {
+            if let RawNoteInsn::Slide(params) = &mut *notes[0] {
+                params.tracks[0].groups[0].dur = dur;
+            }
+        }
+        let err = MaidataWriter::new().write_insns(&insns).unwrap_err();
+        assert_eq!(*err, PrintError::BpmStopTimeWithBeats);
+
+        // positions too fine to be kept exact end the wrapping of measures
+        let source = "(120){999983}1,{999979}1,{999961}1,{999959}1,{999953}1,{1}1,";
+        let (insns, _) = crate::container::parse_maidata_insns(source);
+        assert_eq!(
+            MaidataWriter::new().write_insns(&insns).unwrap(),
+            format!("{}\n", source)
+        );
+    }
+
+    /// Note strings are written in a canonical form, so they are compared as instructions only.
+    fn without_note_strings(maidata: &Maidata) -> Maidata {
+        let mut result = maidata.clone();
+        result.metadata.retain(|x| !x.key.starts_with("inote_"));
+        result
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "\u{feff}&title=a \\＆ b\n&wholebpm=150\n&first=0.5\n&foo=bar\n\
+                     &lv_5=13+\n&inote_5=(150){8}1-4q7-2[2:3],,,,,,Ch[4:1],1bx/2h,\n\
+                     {16}3!>1[160#2.0]*-5[3.0##4:1],,,, ||x\n,,,,,\
+                     ,,,,,,,,E\n&des_5=someone\n&amsg_time_5=1,2\n&amsg_content_5=a,b\n";
+        let (maidata, state) = lex_maidata(input);
+        assert!(state.errors.is_empty(), "{:?}", state);
+
+        let output = MaidataWriter::new().write(&maidata).unwrap();
+        let (written, written_state) = lex_maidata(&output);
+        assert!(written_state.errors.is_empty(), "{:?}", written_state);
+        assert_eq!(written_state.warnings.len(), state.warnings.len());
+        assert_eq!(
+            without_note_strings(&written),
+            without_note_strings(&maidata)
+        );
+        assert_eq!(MaidataWriter::new().write(&written).unwrap(), output);
+    }
+}
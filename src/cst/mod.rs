@@ -0,0 +1,656 @@
+//! Lossless concrete syntax tree of maidata note strings.
+//!
+//! Every byte of the input belongs to exactly one leaf node, trivia included, so the tree can be
+//! edited in place and written back verbatim. Notes are broken down into tokens like keys,
+//! modifiers and durations. Use [`Cst::lower`] to get the [`RawInsn`] AST.
+
+use crate::insn::{RawInsn, SpRawInsn};
+use crate::parser::{
+    parse_one_maidata_insn, t_comment, NomSpan, PError, PResult, PWarning, Span, State,
+};
+use crate::Sp;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SyntaxKind {
+    /// Whitespaces and line breaks.
+    Whitespace,
+    /// `||xxx`, not including the line break.
+    Comment,
+
+    Bpm,
+    BeatDivisor,
+    Rest,
+    Notes,
+    PseudoEach,
+    EndMark,
+    /// An instruction that was recognized but could not be parsed.
+    Error,
+    /// Characters not starting any instruction, or not making sense inside a note.
+    Unknown,
+
+    /// One note inside [`Notes`](Self::Notes) or [`PseudoEach`](Self::PseudoEach).
+    Note,
+    /// `/` between notes.
+    NoteSep,
+    /// `` ` `` between pseudo EACH groups.
+    PseudoEachSep,
+    /// `,` ending a bundle.
+    BundleEnd,
+
+    /// Key of a tap, hold or slide, or a key a slide segment goes through or to.
+    Key,
+    /// Sensor of a touch or touch hold, like `C` or `B1`.
+    Sensor,
+    /// One modifier, like `b` or `$$`.
+    Modifier,
+    /// `h` of holds and touch holds.
+    HoldMarker,
+    /// Shape of a slide segment, like `-` or `pp`.
+    SlideShape,
+    /// `*` between the tracks of a slide.
+    TrackSep,
+    /// `[...]`, with the tokens below as children.
+    Duration,
+    LBracket,
+    RBracket,
+    /// A number inside a duration, like `4` or `1.5`.
+    Number,
+    /// `:` between the divisor and the number of beats.
+    Colon,
+    /// `#` or `##`.
+    Hash,
+}
+
+impl SyntaxKind {
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::Comment)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum NodeContent {
+    Leaf(String),
+    Interior(Vec<SyntaxNode>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SyntaxNode {
+    kind: SyntaxKind,
+    span: Span,
+    content: NodeContent,
+    // top-level nodes only
+    lowered: Option<Box<Lowered>>,
+}
+
+/// What a top-level node lowers to, as long as its text is unchanged.
+#[derive(Clone, PartialEq, Debug)]
+struct Lowered {
+    text: String,
+    insns: Vec<SpRawInsn>,
+    warnings: Vec<Sp<PWarning>>,
+    errors: Vec<Sp<PError>>,
+}
+
+impl SyntaxNode {
+    fn leaf(kind: SyntaxKind, text: NomSpan) -> Self {
+        Self {
+            kind,
+            span: Span::from_fragment(text),
+            content: NodeContent::Leaf(text.fragment().to_string()),
+            lowered: None,
+        }
+    }
+
+    fn interior(kind: SyntaxKind, text: NomSpan, children: Vec<SyntaxNode>) -> Self {
+        Self {
+            kind,
+            span: Span::from_fragment(text),
+            content: NodeContent::Interior(children),
+            lowered: None,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    /// Span of the node in the original input. Not updated by edits.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(self.content, NodeContent::Leaf(_))
+    }
+
+    pub fn children(&self) -> &[SyntaxNode] {
+        match &self.content {
+            NodeContent::Leaf(_) => &[],
+            NodeContent::Interior(children) => children,
+        }
+    }
+
+    pub fn children_mut(&mut self) -> &mut [SyntaxNode] {
+        match &mut self.content {
+            NodeContent::Leaf(_) => &mut [],
+            NodeContent::Interior(children) => children,
+        }
+    }
+
+    /// The source text covered by this node.
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+
+    /// Replace the source text of this node, dropping its children if any.
+    pub fn set_text<S: Into<String>>(&mut self, text: S) {
+        self.content = NodeContent::Leaf(text.into());
+    }
+
+    /// Lower a top-level node, parsing it again only if its text has been edited.
+    fn lower(&self, insns: &mut Vec<SpRawInsn>, state: &mut State) {
+        let text = self.text();
+        if let Some(lowered) = self.lowered.as_ref().filter(|x| x.text == text) {
+            insns.extend(lowered.insns.iter().cloned());
+            state.warnings.extend(lowered.warnings.iter().cloned());
+            state.errors.extend(lowered.errors.iter().cloned());
+            return;
+        }
+
+        // spans of the edited text start where the node did
+        let (edited_insns, edited_state) = crate::container::parse_maidata_insns(&text);
+        insns.extend(edited_insns.into_iter().map(|x| offset_insn(x, self.span)));
+        state
+            .warnings
+            .extend(edited_state.warnings.into_iter().map(|mut x| {
+                *x.span_mut() = x.span().offset_by(self.span);
+                x
+            }));
+        state
+            .errors
+            .extend(edited_state.errors.into_iter().map(|mut x| {
+                *x.span_mut() = x.span().offset_by(self.span);
+                x
+            }));
+    }
+}
+
+impl std::fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.content {
+            NodeContent::Leaf(text) => write!(f, "{}", text),
+            NodeContent::Interior(children) => {
+                for child in children {
+                    write!(f, "{}", child)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Concrete syntax tree of a whole note string, e.g. the value of `inote_N`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Cst {
+    nodes: Vec<SyntaxNode>,
+}
+
+impl Cst {
+    /// Top-level nodes, in source order.
+    pub fn nodes(&self) -> &[SyntaxNode] {
+        &self.nodes
+    }
+
+    pub fn nodes_mut(&mut self) -> &mut Vec<SyntaxNode> {
+        &mut self.nodes
+    }
+
+    /// Iterate over the top-level nodes that are not trivia.
+    pub fn iter_insns(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.nodes.iter().filter(|node| !node.kind.is_trivia())
+    }
+
+    /// The source text covered by the tree, identical to the input if not edited.
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+
+    /// Lower into the [`RawInsn`] AST, with the messages of the nodes.
+    ///
+    /// Each top-level node keeps the instructions it was parsed into, so only edited nodes are
+    /// parsed again. Spans are those of the original input; the ones in an edited node start
+    /// where the node did.
+    pub fn lower(&self) -> (Vec<SpRawInsn>, State) {
+        let mut insns = vec![];
+        let mut state = State::default();
+        for node in &self.nodes {
+            node.lower(&mut insns, &mut state);
+        }
+        (insns, state)
+    }
+}
+
+impl std::fmt::Display for Cst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_maidata_cst(mut s: NomSpan) -> PResult<Cst> {
+    use nom::character::complete::multispace1;
+    use nom::Offset;
+    use nom::Slice;
+
+    let mut nodes: Vec<SyntaxNode> = vec![];
+    while !s.fragment().is_empty() {
+        if let Ok((rest, ws)) = multispace1::<_, nom::error::Error<_>>(s) {
+            nodes.push(trivia_node(SyntaxKind::Whitespace, ws));
+            s = rest;
+            continue;
+        }
+        if let Ok((rest, _)) = t_comment(s) {
+            let text = s.slice(..s.offset(&rest));
+            nodes.push(trivia_node(SyntaxKind::Comment, text));
+            s = rest;
+            continue;
+        }
+
+        let checkpoint = s.extra.borrow().checkpoint();
+        let (rest, insn) = parse_one_maidata_insn(s)?;
+        let text = s.slice(..s.offset(&rest));
+        let lowered = {
+            let state = s.extra.borrow();
+            Lowered {
+                text: text.fragment().to_string(),
+                insns: insn.iter().cloned().collect(),
+                warnings: state.warnings[checkpoint.0..].to_vec(),
+                errors: state.errors[checkpoint.1..].to_vec(),
+            }
+        };
+        let mut node = match insn {
+            Some(insn) => insn_node(text, &insn),
+            None => {
+                // unknown characters are consumed one at a time, merge them
+                let kind = if text.fragment().chars().count() == 1 {
+                    SyntaxKind::Unknown
+                } else {
+                    SyntaxKind::Error
+                };
+                match nodes.last_mut() {
+                    Some(last) if kind == SyntaxKind::Unknown && last.kind == kind => {
+                        if let NodeContent::Leaf(last_text) = &mut last.content {
+                            last_text.push_str(text.fragment());
+                        }
+                        if let Some(last_lowered) = &mut last.lowered {
+                            last_lowered.text.push_str(text.fragment());
+                            last_lowered.errors.extend(lowered.errors);
+                        }
+                        last.span.len += text.fragment().len();
+                        let span = Span::from_fragment(text);
+                        last.span.end_line = span.end_line;
+                        last.span.end_col = span.end_col;
+                        s = rest;
+                        continue;
+                    }
+                    _ => SyntaxNode::leaf(kind, text),
+                }
+            }
+        };
+        node.lowered = Some(Box::new(lowered));
+        nodes.push(node);
+        s = rest;
+    }
+
+    Ok((s, Cst { nodes }))
+}
+
+fn trivia_node(kind: SyntaxKind, text: NomSpan) -> SyntaxNode {
+    let mut node = SyntaxNode::leaf(kind, text);
+    node.lowered = Some(Box::new(Lowered {
+        text: text.fragment().to_string(),
+        insns: vec![],
+        warnings: vec![],
+        errors: vec![],
+    }));
+    node
+}
+
+fn insn_node(text: NomSpan, insn: &SpRawInsn) -> SyntaxNode {
+    let notes = match &**insn {
+        RawInsn::Bpm(_) => return SyntaxNode::leaf(SyntaxKind::Bpm, text),
+        RawInsn::BeatDivisor(_) => return SyntaxNode::leaf(SyntaxKind::BeatDivisor, text),
+        RawInsn::Rest => return SyntaxNode::leaf(SyntaxKind::Rest, text),
+        RawInsn::EndMark => return SyntaxNode::leaf(SyntaxKind::EndMark, text),
+        RawInsn::Notes(notes) => notes.iter().collect::<Vec<_>>(),
+        RawInsn::PseudoEach(groups) => groups.iter().flatten().collect::<Vec<_>>(),
+    };
+    let kind = match &**insn {
+        RawInsn::PseudoEach(_) => SyntaxKind::PseudoEach,
+        _ => SyntaxKind::Notes,
+    };
+
+    // notes are taken by their spans, everything in between are separators and trivia
+    let base = text.location_offset();
+    let mut children = vec![];
+    let mut pos = 0;
+    for note in notes {
+        let start = note.span().byte_offset - base;
+        let end = start + note.span().len;
+        children.extend(bundle_gap_nodes(text, pos, start));
+        let note_text = slice(text, start, end);
+        children.push(SyntaxNode::interior(
+            SyntaxKind::Note,
+            note_text,
+            note_tokens(note_text),
+        ));
+        pos = end;
+    }
+    children.extend(bundle_gap_nodes(text, pos, text.fragment().len()));
+
+    SyntaxNode::interior(kind, text, children)
+}
+
+/// Tokenize the text between two notes of a bundle.
+fn bundle_gap_nodes(text: NomSpan, start: usize, end: usize) -> Vec<SyntaxNode> {
+    let mut result = vec![];
+    let gap = &text.fragment()[start..end];
+    let mut chars = gap.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let kind = match c {
+            '/' => SyntaxKind::NoteSep,
+            '`' => SyntaxKind::PseudoEachSep,
+            ',' => SyntaxKind::BundleEnd,
+            _ if c.is_whitespace() => SyntaxKind::Whitespace,
+            _ => SyntaxKind::Unknown,
+        };
+        let mut j = i + c.len_utf8();
+        if matches!(kind, SyntaxKind::Whitespace | SyntaxKind::Unknown) {
+            while let Some(&(k, next)) = chars.peek() {
+                let same = if kind == SyntaxKind::Whitespace {
+                    next.is_whitespace()
+                } else {
+                    !next.is_whitespace() && !matches!(next, '/' | '`' | ',')
+                };
+                if !same {
+                    break;
+                }
+                j = k + next.len_utf8();
+                chars.next();
+            }
+        }
+        result.push(SyntaxNode::leaf(kind, slice(text, start + i, start + j)));
+    }
+    result
+}
+
+/// Tokenize the text of one note, which the parser has accepted.
+fn note_tokens(text: NomSpan) -> Vec<SyntaxNode> {
+    use SyntaxKind::*;
+
+    let x = *text.fragment();
+    let mut result = vec![];
+    let mut i = 0;
+    while let Some(c) = x[i..].chars().next() {
+        let rest = &x[i..];
+        if c == '[' {
+            let end = rest.find(']').map_or(x.len(), |j| i + j + 1);
+            let dur_text = slice(text, i, end);
+            result.push(SyntaxNode::interior(
+                Duration,
+                dur_text,
+                duration_tokens(dur_text),
+            ));
+            i = end;
+            continue;
+        }
+
+        let starts_with_digit = |n: usize| rest[n..].starts_with(|c: char| c.is_ascii_digit());
+        let (kind, len) = match c {
+            '1'..='8' => (Key, 1),
+            'A'..='E' if starts_with_digit(1) => (Sensor, 2),
+            'A'..='E' => (Sensor, 1),
+            'h' => (HoldMarker, 1),
+            '*' => (TrackSep, 1),
+            '$' if rest.starts_with("$$") => (Modifier, 2),
+            'b' | 'x' | 'f' | '$' | '@' | '?' | '!' => (Modifier, 1),
+            'p' if rest.starts_with("pp") => (SlideShape, 2),
+            'q' if rest.starts_with("qq") => (SlideShape, 2),
+            '-' | '^' | '<' | '>' | 'v' | 'p' | 'q' | 's' | 'z' | 'V' | 'w' => (SlideShape, 1),
+            _ if c.is_whitespace() => (Whitespace, whitespace_len(rest)),
+            _ => (Unknown, c.len_utf8()),
+        };
+        result.push(SyntaxNode::leaf(kind, slice(text, i, i + len)));
+        i += len;
+    }
+    result
+}
+
+/// Tokenize `[...]`, the closing bracket may be missing.
+fn duration_tokens(text: NomSpan) -> Vec<SyntaxNode> {
+    use SyntaxKind::*;
+
+    let x = *text.fragment();
+    let mut result = vec![];
+    let mut i = 0;
+    while let Some(c) = x[i..].chars().next() {
+        let rest = &x[i..];
+        let (kind, len) = match c {
+            '[' => (LBracket, 1),
+            ']' => (RBracket, 1),
+            ':' => (Colon, 1),
+            '#' if rest.starts_with("##") => (Hash, 2),
+            '#' => (Hash, 1),
+            '0'..='9' | '.' => (
+                Number,
+                rest.find(|c: char| !matches!(c, '0'..='9' | '.'))
+                    .unwrap_or(rest.len()),
+            ),
+            _ if c.is_whitespace() => (Whitespace, whitespace_len(rest)),
+            _ => (Unknown, c.len_utf8()),
+        };
+        result.push(SyntaxNode::leaf(kind, slice(text, i, i + len)));
+        i += len;
+    }
+    result
+}
+
+fn whitespace_len(x: &str) -> usize {
+    x.find(|c: char| !c.is_whitespace()).unwrap_or(x.len())
+}
+
+fn slice(text: NomSpan, start: usize, end: usize) -> NomSpan {
+    use nom::Slice;
+
+    text.slice(start..end)
+}
+
+/// Move the spans of an instruction parsed on its own to where its text starts at `base`.
+fn offset_insn(mut insn: SpRawInsn, base: Span) -> SpRawInsn {
+    *insn.span_mut() = insn.span().offset_by(base);
+    let notes: Vec<_> = match &mut *insn {
+        RawInsn::Notes(notes) => notes.iter_mut().collect(),
+        RawInsn::PseudoEach(groups) => groups.iter_mut().flatten().collect(),
+        _ => vec![],
+    };
+    for note in notes {
+        *note.span_mut() = note.span().offset_by(base);
+    }
+    insn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{parse_maidata_cst, parse_maidata_insns};
+
+    fn spans(insns: &[SpRawInsn]) -> Vec<Span> {
+        let mut result = vec![];
+        for insn in insns {
+            result.push(insn.span());
+            match &**insn {
+                RawInsn::Notes(notes) => result.extend(notes.iter().map(|x| x.span())),
+                RawInsn::PseudoEach(groups) => {
+                    result.extend(groups.iter().flatten().map(|x| x.span()))
+                }
+                _ => (),
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_lossless() {
+        let inputs = [
+            "",
+            "(120){4}\n1,2 / 3 ,\n||comment\n  1-5[4:1] ,E\n",
+            "( 120 ) { 4 }1bx$/ B1f ,,1h [ 4:1 ]`Ch,",
+            "1,@@@2,(0),  zz\r\n12,",
+            "1-4[8:1]q7[8:3]*V35[160#2.0],1b-5[3##4:1]b/2@?,",
+        ];
+        for input in inputs {
+            let (cst, state) = parse_maidata_cst(input);
+            assert_eq!(cst.text(), input);
+            let (lowered, lowered_state) = cst.lower();
+            let (insns, _) = parse_maidata_insns(input);
+            assert_eq!(lowered, insns);
+            assert_eq!(spans(&lowered), spans(&insns));
+            assert_eq!(lowered_state.errors, state.errors);
+            assert_eq!(lowered_state.warnings, state.warnings);
+        }
+    }
+
+    #[test]
+    fn test_structure() {
+        let (cst, state) = parse_maidata_cst("(120) 1/2`3, ||x\n@@");
+        assert_eq!(state.errors.len(), 2);
+        let kinds = cst.nodes().iter().map(|x| x.kind()).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                SyntaxKind::Bpm,
+                SyntaxKind::Whitespace,
+                SyntaxKind::PseudoEach,
+                SyntaxKind::Whitespace,
+                SyntaxKind::Comment,
+                SyntaxKind::Whitespace,
+                SyntaxKind::Unknown,
+            ]
+        );
+        let bundle = &cst.nodes()[2];
+        let kinds = bundle
+            .children()
+            .iter()
+            .map(|x| x.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                SyntaxKind::Note,
+                SyntaxKind::NoteSep,
+                SyntaxKind::Note,
+                SyntaxKind::PseudoEachSep,
+                SyntaxKind::Note,
+                SyntaxKind::BundleEnd,
+            ]
+        );
+        assert_eq!(bundle.children()[4].span().col, 11);
+        assert_eq!(cst.nodes()[6].text(), "@@");
+    }
+
+    #[test]
+    fn test_note_tokens() {
+        let tokens = |input: &str| {
+            let (cst, _) = parse_maidata_cst(input);
+            let note = &cst.nodes()[0].children()[0];
+            assert_eq!(note.kind(), SyntaxKind::Note);
+            note.children()
+                .iter()
+                .map(|x| (x.kind(), x.text()))
+                .collect::<Vec<_>>()
+        };
+        use SyntaxKind::*;
+
+        assert_eq!(
+            tokens("1b$$,"),
+            vec![
+                (Key, "1".to_string()),
+                (Modifier, "b".to_string()),
+                (Modifier, "$$".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokens("B1fh,"),
+            vec![
+                (Sensor, "B1".to_string()),
+                (Modifier, "f".to_string()),
+                (HoldMarker, "h".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokens("1pp5[8:1]*V35,"),
+            vec![
+                (Key, "1".to_string()),
+                (SlideShape, "pp".to_string()),
+                (Key, "5".to_string()),
+                (Duration, "[8:1]".to_string()),
+                (TrackSep, "*".to_string()),
+                (SlideShape, "V".to_string()),
+                (Key, "3".to_string()),
+                (Key, "5".to_string()),
+            ]
+        );
+
+        let (cst, _) = parse_maidata_cst("1-5[ 3.0##160#4:1 ],");
+        let dur = &cst.nodes()[0].children()[0].children()[3];
+        let tokens = dur
+            .children()
+            .iter()
+            .map(|x| (x.kind(), x.text()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                (LBracket, "[".to_string()),
+                (Whitespace, " ".to_string()),
+                (Number, "3.0".to_string()),
+                (Hash, "##".to_string()),
+                (Number, "160".to_string()),
+                (Hash, "#".to_string()),
+                (Number, "4".to_string()),
+                (Colon, ":".to_string()),
+                (Number, "1".to_string()),
+                (Whitespace, " ".to_string()),
+                (RBracket, "]".to_string()),
+            ]
+        );
+        assert_eq!(dur.children()[6].span().col, 15);
+    }
+
+    #[test]
+    fn test_edit() {
+        let (mut cst, _) = parse_maidata_cst("(120){4}1b,\n  2,\n");
+        let note = &mut cst.nodes_mut()[2].children_mut()[0];
+        assert_eq!(note.text(), "1b");
+        note.children_mut()[0].set_text("3");
+        note.children_mut()[1].set_text("x");
+        assert_eq!(cst.text(), "(120){4}3x,\n  2,\n");
+        let (insns, state) = cst.lower();
+        assert!(!state.has_messages());
+        assert_eq!(insns, parse_maidata_insns("(120){4}3x,\n  2,\n").0);
+        // unedited nodes keep their spans, edited ones start where they did
+        assert_eq!(insns[2].span().col, 9);
+        assert_eq!(insns[3].span().line, 2);
+        assert_eq!(insns[3].span().col, 3);
+
+        let note = &mut cst.nodes_mut()[4].children_mut()[0];
+        note.set_text("2-9[4:1]");
+        let (insns, state) = cst.lower();
+        assert_eq!(insns.len(), 3);
+        assert_eq!(state.errors.len(), 1);
+        assert_eq!(state.errors[0].span().line, 2);
+        assert_eq!(state.errors[0].span().col, 3);
+    }
+}
@@ -1,8 +1,10 @@
 mod directives_ty;
 mod note_ty;
+mod printable;
 
 pub use directives_ty::*;
 pub use note_ty::*;
+pub use printable::PrintError;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
@@ -54,8 +56,34 @@ pub enum RawInsn {
     BeatDivisor(BeatDivisorParams),
     Rest,
     Notes(crate::VecSp<RawNoteInsn>),
+    /// Pseudo EACH ``1`2`3`4,``: groups of notes played one right after another.
+    PseudoEach(Vec<crate::VecSp<RawNoteInsn>>),
     EndMark,
 }
 
+impl std::fmt::Display for RawInsn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |notes: &crate::VecSp<RawNoteInsn>| {
+            notes
+                .iter()
+                .map(|x| format!("{}", **x))
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        match self {
+            Self::Bpm(params) => write!(f, "({})", params),
+            Self::BeatDivisor(params) => write!(f, "{{{}}}", params),
+            Self::Rest => write!(f, ","),
+            Self::Notes(notes) => write!(f, "{},", join(notes)),
+            Self::PseudoEach(groups) => write!(
+                f,
+                "{},",
+                groups.iter().map(join).collect::<Vec<_>>().join("`")
+            ),
+            Self::EndMark => write!(f, "E"),
+        }
+    }
+}
+
 pub type SpRawInsn = crate::Sp<RawInsn>;
 pub type SpRawNoteInsn = crate::Sp<RawNoteInsn>;
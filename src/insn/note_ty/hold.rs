@@ -21,12 +21,17 @@ impl std::fmt::Display for HoldModifier {
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct HoldParams {
     pub key: Key,
-    pub dur: Duration,
+    /// `None` for the no-duration form, e.g. `1h,`.
+    pub dur: Option<Duration>,
     pub modifier: HoldModifier,
 }
 
 impl std::fmt::Display for HoldParams {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}h[{}]", self.key, self.modifier, self.dur)
+        write!(f, "{}{}h", self.key, self.modifier)?;
+        if let Some(dur) = self.dur {
+            write!(f, "[{}]", dur)?;
+        }
+        Ok(())
     }
 }
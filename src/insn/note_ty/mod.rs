@@ -10,6 +10,7 @@ pub use tap::*;
 pub use touch::*;
 pub use touch_hold::*;
 
+use crate::materialize::Rational;
 use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -205,13 +206,21 @@ impl std::ops::Add<Duration> for Duration {
                     }
                     a
                 };
-                let divisor = lhs.divisor / gcd(lhs.divisor, rhs.divisor) * rhs.divisor;
-                let num = lhs.num * (divisor / lhs.divisor) + rhs.num * (divisor / rhs.divisor);
-                let gcd = gcd(num, divisor);
+                // `None` if the sum is too fine to be kept exact
+                let divisor =
+                    (lhs.divisor / gcd(lhs.divisor, rhs.divisor)).checked_mul(rhs.divisor)?;
+                let scale = |x: u32| Rational::from(i64::from(divisor / x));
+                let num = (lhs.num.checked_mul(scale(lhs.divisor))?)
+                    .checked_add(rhs.num.checked_mul(scale(rhs.divisor))?)?;
+                // fractional beat counts are kept over the common divisor
+                let gcd = match u32::try_from(num.num()) {
+                    Ok(x) if num.den() == 1 => gcd(x, divisor),
+                    _ => 1,
+                };
                 Some(Self::NumBeats(NumBeatsParams {
                     bpm: lhs.bpm.or(rhs.bpm),
                     divisor: divisor / gcd,
-                    num: num / gcd,
+                    num: num * Rational::new(1, i64::from(gcd)),
                 }))
             }
             (Self::Seconds(lhs), Self::Seconds(rhs)) => Some(Self::Seconds(lhs + rhs)),
@@ -233,7 +242,9 @@ impl std::fmt::Display for Duration {
 pub struct NumBeatsParams {
     pub bpm: Option<f64>,
     pub divisor: u32,
-    pub num: u32,
+    /// Number of beats, which may be fractional as in `[8:1.5]`. Kept exact so that the
+    /// durations of slide groups add up without rounding.
+    pub num: Rational,
 }
 
 impl std::fmt::Display for NumBeatsParams {
@@ -241,6 +252,46 @@ impl std::fmt::Display for NumBeatsParams {
         if let Some(bpm) = self.bpm {
             write!(f, "{}#", bpm)?;
         }
-        write!(f, "{}:{}", self.divisor, self.num)
+        write!(f, "{}:", self.divisor)?;
+        match to_decimal(self.num) {
+            Some((digits, 0)) => write!(f, "{}", digits),
+            Some((digits, places)) => {
+                let scale = 10i128.pow(places);
+                let sign = if digits < 0 { "-" } else { "" };
+                let (int, frac) = (digits.abs() / scale, digits.abs() % scale);
+                write!(
+                    f,
+                    "{}{}.{:0width$}",
+                    sign,
+                    int,
+                    frac,
+                    width = places as usize
+                )
+            }
+            // e.g. 1/3, rejected by `check_printable`
+            None => write!(f, "{}", self.num.to_f64()),
+        }
+    }
+}
+
+/// `x` as an integer and the number of decimal places to shift it by, `None` if `x` has no
+/// finite decimal expansion or it doesn't fit.
+pub(crate) fn to_decimal(x: Rational) -> Option<(i128, u32)> {
+    let mut den = x.den();
+    let (mut twos, mut fives) = (0, 0);
+    while den % 2 == 0 {
+        den /= 2;
+        twos += 1;
+    }
+    while den % 5 == 0 {
+        den /= 5;
+        fives += 1;
+    }
+    if den != 1 {
+        return None;
     }
+    let places = u32::max(twos, fives);
+    let scale = 10i128.checked_pow(places)?;
+    let digits = i128::from(x.num()).checked_mul(scale / i128::from(x.den()))?;
+    Some((digits, places))
 }
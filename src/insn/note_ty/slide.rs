@@ -66,20 +66,15 @@ impl std::ops::Add<SlideDuration> for SlideDuration {
 
 impl std::fmt::Display for SlideDuration {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if !self.valid() {
-            // panic!("Invalid slide duration spec: {:?} {:?}", spec, duration);
-            return Err(std::fmt::Error);
-        }
         match self {
             Self::Simple(duration) => write!(f, "{}", duration),
             Self::Custom(spec, duration) => match spec {
-                SlideStopTimeSpec::Bpm(bpm) => {
-                    if let Duration::Seconds(seconds) = duration {
-                        write!(f, "{}#{}", bpm, seconds)
-                    } else {
-                        unreachable!()
-                    }
-                }
+                SlideStopTimeSpec::Bpm(bpm) => match duration {
+                    Duration::Seconds(seconds) => write!(f, "{}#{}", bpm, seconds),
+                    // `[bpm#x:y]` means something else, and there is no syntax for this, see
+                    // `check_printable`; the stop time in seconds materializes identically
+                    _ => write!(f, "{}##{}", 60.0 / bpm, duration),
+                },
                 SlideStopTimeSpec::Seconds(seconds) => match duration {
                     Duration::Seconds(dur) => write!(f, "{}##{}", seconds, dur),
                     _ => write!(f, "{}##{}", seconds, duration),
@@ -134,19 +129,58 @@ impl std::fmt::Display for SlideTrackModifier {
     }
 }
 
+/// Consecutive segments sharing one duration, e.g. `-4q7[8:3]` in `1-4q7[8:3]`.
 #[derive(Clone, PartialEq, Debug)]
-pub struct SlideTrack {
+pub struct SlideSegmentGroup {
     pub segments: Vec<SlideSegment>,
     pub dur: SlideDuration,
-    pub modifier: SlideTrackModifier,
 }
 
-impl std::fmt::Display for SlideTrack {
+impl std::fmt::Display for SlideSegmentGroup {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for segment in self.segments.iter() {
             write!(f, "{}", segment)?;
         }
-        write!(f, "[{}]{}", self.dur, self.modifier)
+        write!(f, "[{}]", self.dur)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SlideTrack {
+    /// Usually a single group. Chained slides like `1-4[8:1]q7[8:3]` have one group per
+    /// duration, so that each part moves at its own speed.
+    pub groups: Vec<SlideSegmentGroup>,
+    pub modifier: SlideTrackModifier,
+}
+
+impl SlideTrack {
+    /// All segments of all groups, in order.
+    pub fn segments(&self) -> impl Iterator<Item = &SlideSegment> {
+        self.groups.iter().flat_map(|group| group.segments.iter())
+    }
+
+    pub fn segments_mut(&mut self) -> impl Iterator<Item = &mut SlideSegment> {
+        self.groups
+            .iter_mut()
+            .flat_map(|group| group.segments.iter_mut())
+    }
+
+    /// The duration of the whole track. The stop time comes from the first group. `None` if
+    /// the groups' durations can't be added up, e.g. beats and seconds or beats too fine to be
+    /// kept exact, or if there are no groups.
+    pub fn dur(&self) -> Option<SlideDuration> {
+        let mut groups = self.groups.iter();
+        let first = groups.next()?.dur;
+        groups.try_fold(first, |acc, group| acc + group.dur)
+    }
+}
+
+impl std::fmt::Display for SlideTrack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for group in self.groups.iter() {
+            write!(f, "{}", group)?;
+        }
+        write!(f, "{}", self.modifier)
     }
 }
 
@@ -181,7 +215,11 @@ impl std::fmt::Display for SlideSegment {
             Self::Z(params) => write!(f, "z{}", params.destination),
             Self::Pp(params) => write!(f, "pp{}", params.destination),
             Self::Qq(params) => write!(f, "qq{}", params.destination),
-            Self::Angle(params) => write!(f, "V{}{}", params.interim.unwrap(), params.destination),
+            // without an interim key this doesn't parse back, see `check_printable`
+            Self::Angle(params) => match params.interim {
+                Some(interim) => write!(f, "V{}{}", interim, params.destination),
+                None => write!(f, "V{}", params.destination),
+            },
             Self::Spread(params) => write!(f, "w{}", params.destination),
         }
     }
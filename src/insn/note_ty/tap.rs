@@ -1,11 +1,18 @@
 use super::*;
 
+/// A tap shape forced by a modifier. Without one, taps are rings and slide heads are stars.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum TapShape {
+    /// `@`, only on slide heads.
     Ring,
+    /// `$`, only on taps.
     Star,
+    /// `$$`, only on taps.
     StarSpin,
-    Invalid,
+    /// `?`, a slide without a head, only on slide heads.
+    NoStar,
+    /// `!`, a slide without a head that appears all at once, only on slide heads.
+    Sudden,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
@@ -23,7 +30,14 @@ impl std::fmt::Display for TapModifier {
         if self.is_ex {
             write!(f, "x")?;
         }
-        Ok(())
+        match self.shape {
+            Some(TapShape::Ring) => write!(f, "@"),
+            Some(TapShape::Star) => write!(f, "$"),
+            Some(TapShape::StarSpin) => write!(f, "$$"),
+            Some(TapShape::NoStar) => write!(f, "?"),
+            Some(TapShape::Sudden) => write!(f, "!"),
+            None => Ok(()),
+        }
     }
 }
 
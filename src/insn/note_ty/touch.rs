@@ -2,11 +2,19 @@ use super::*;
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
 pub struct TouchModifier {
+    pub is_break: bool,
+    pub is_ex: bool,
     pub is_firework: bool,
 }
 
 impl std::fmt::Display for TouchModifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_break {
+            write!(f, "b")?;
+        }
+        if self.is_ex {
+            write!(f, "x")?;
+        }
         if self.is_firework {
             write!(f, "f")?;
         }
@@ -2,11 +2,19 @@ use super::*;
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
 pub struct TouchHoldModifier {
+    pub is_break: bool,
+    pub is_ex: bool,
     pub is_firework: bool,
 }
 
 impl std::fmt::Display for TouchHoldModifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_break {
+            write!(f, "b")?;
+        }
+        if self.is_ex {
+            write!(f, "x")?;
+        }
         if self.is_firework {
             write!(f, "f")?;
         }
@@ -17,12 +25,17 @@ impl std::fmt::Display for TouchHoldModifier {
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct TouchHoldParams {
     pub sensor: TouchSensor,
-    pub dur: Duration,
+    /// `None` for the no-duration form, e.g. `Ch,`.
+    pub dur: Option<Duration>,
     pub modifier: TouchHoldModifier,
 }
 
 impl std::fmt::Display for TouchHoldParams {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}h[{}]", self.sensor, self.modifier, self.dur)
+        write!(f, "{}{}h", self.sensor, self.modifier)?;
+        if let Some(dur) = self.dur {
+            write!(f, "[{}]", dur)?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,492 @@
+//! Checking whether instructions can be written back as maidata text.
+//!
+//! The `Display` impls of the instruction types are best-effort: some values representable in the
+//! AST have no maidata syntax, or would be parsed back as something else. [`RawInsn::check_printable`]
+//! tells these apart, so `parse(insn.to_string()) == insn` holds whenever it returns `Ok`.
+
+use super::*;
+use crate::materialize::Rational;
+
+/// Reasons an instruction can't be written as maidata text that parses back to itself.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PrintError {
+    /// A `Notes` bundle without any note; it would be written as a rest.
+    EmptyBundle,
+    /// A pseudo EACH with less than two groups, or with an empty group.
+    InvalidPseudoEach,
+    /// A non-finite, negative or otherwise out-of-range number, or a beat count without a finite
+    /// decimal expansion.
+    InvalidValue(f64),
+    /// A beat divisor of zero.
+    ZeroDivisor,
+    InvalidTouchSensor(TouchSensor),
+    /// A tap shape modifier that is not allowed on this kind of note.
+    InvalidShape(NoteType, TapShape),
+    /// A slide without any track.
+    NoSlideTracks,
+    /// A slide track without any segment.
+    EmptySlideTrack,
+    /// A `V` segment without its interim key, or another segment with one.
+    InvalidInterim(SlideSegment),
+    /// A slide track that the parser would reject, e.g. `1-2`.
+    InvalidSlideTrack(String),
+    /// Tracks of a slide disagreeing on the sudden (`!`) modifier, with each other or with the
+    /// head shape.
+    InvalidSudden,
+    /// A slide stop time in BPM combined with a duration in beats, which has no syntax:
+    /// `[160#4:1]` is a duration in beats at BPM 160.
+    BpmStopTimeWithBeats,
+}
+
+impl std::fmt::Display for PrintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrintError::EmptyBundle => write!(f, "empty note bundle"),
+            PrintError::InvalidPseudoEach => write!(f, "pseudo EACH needs two or more groups"),
+            PrintError::InvalidValue(x) => write!(f, "invalid value: {}", x),
+            PrintError::ZeroDivisor => write!(f, "beat divisor must not be zero"),
+            PrintError::InvalidTouchSensor(x) => write!(f, "invalid touch sensor: {:?}", x),
+            PrintError::InvalidShape(note_type, shape) => {
+                write!(f, "{:?} shape is not allowed for {}", shape, note_type)
+            }
+            PrintError::NoSlideTracks => write!(f, "slide without tracks"),
+            PrintError::EmptySlideTrack => write!(f, "slide track without segments"),
+            PrintError::InvalidInterim(x) => write!(f, "invalid interim key of {:?}", x),
+            PrintError::InvalidSlideTrack(x) => write!(f, "invalid slide track: {}", x),
+            PrintError::InvalidSudden => write!(f, "inconsistent sudden modifier"),
+            PrintError::BpmStopTimeWithBeats => {
+                write!(f, "slide stop time in BPM with a duration in beats")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrintError {}
+
+fn check_value(x: f64, allow_zero: bool) -> Result<(), PrintError> {
+    // `-0` is rejected too, as the parser doesn't accept signs
+    if !x.is_finite() || x.is_sign_negative() || (!allow_zero && x == 0.0) {
+        return Err(PrintError::InvalidValue(x));
+    }
+    Ok(())
+}
+
+impl RawInsn {
+    /// Check that [`Display`](std::fmt::Display) writes this instruction in a form that parses
+    /// back to it.
+    pub fn check_printable(&self) -> Result<(), PrintError> {
+        match self {
+            RawInsn::Bpm(params) => check_value(params.new_bpm, false),
+            RawInsn::BeatDivisor(BeatDivisorParams::NewDivisor(x)) => match x {
+                0 => Err(PrintError::ZeroDivisor),
+                _ => Ok(()),
+            },
+            RawInsn::BeatDivisor(BeatDivisorParams::NewAbsoluteDuration(x)) => {
+                check_value(*x, false)
+            }
+            RawInsn::Rest | RawInsn::EndMark => Ok(()),
+            RawInsn::Notes(notes) => {
+                if notes.is_empty() {
+                    return Err(PrintError::EmptyBundle);
+                }
+                notes.iter().try_for_each(|x| x.check_printable())
+            }
+            RawInsn::PseudoEach(groups) => {
+                if groups.len() < 2 || groups.iter().any(|x| x.is_empty()) {
+                    return Err(PrintError::InvalidPseudoEach);
+                }
+                groups
+                    .iter()
+                    .flatten()
+                    .try_for_each(|x| x.check_printable())
+            }
+        }
+    }
+}
+
+impl RawNoteInsn {
+    /// See [`RawInsn::check_printable`].
+    pub fn check_printable(&self) -> Result<(), PrintError> {
+        match self {
+            RawNoteInsn::Tap(params) => match params.modifier.shape {
+                Some(shape @ (TapShape::Ring | TapShape::NoStar | TapShape::Sudden)) => {
+                    Err(PrintError::InvalidShape(NoteType::Tap, shape))
+                }
+                _ => Ok(()),
+            },
+            RawNoteInsn::Touch(params) => check_sensor(params.sensor),
+            RawNoteInsn::Hold(params) => params.dur.map_or(Ok(()), |x| x.check_printable()),
+            RawNoteInsn::TouchHold(params) => {
+                check_sensor(params.sensor)?;
+                params.dur.map_or(Ok(()), |x| x.check_printable())
+            }
+            RawNoteInsn::Slide(params) => params.check_printable(),
+        }
+    }
+}
+
+fn check_sensor(sensor: TouchSensor) -> Result<(), PrintError> {
+    TouchSensor::new(sensor.group(), sensor.index())
+        .map(|_| ())
+        .map_err(|_| PrintError::InvalidTouchSensor(sensor))
+}
+
+impl SlideParams {
+    /// See [`RawInsn::check_printable`].
+    pub fn check_printable(&self) -> Result<(), PrintError> {
+        use crate::transform::normalize::normalize_slide_track;
+
+        if let Some(shape @ (TapShape::Star | TapShape::StarSpin)) = self.start.modifier.shape {
+            return Err(PrintError::InvalidShape(NoteType::Slide, shape));
+        }
+        let is_sudden = match self.tracks.first() {
+            Some(track) => track.modifier.is_sudden,
+            None => return Err(PrintError::NoSlideTracks),
+        };
+        if self
+            .tracks
+            .iter()
+            .any(|x| x.modifier.is_sudden != is_sudden)
+            || is_sudden != (self.start.modifier.shape == Some(TapShape::Sudden))
+        {
+            return Err(PrintError::InvalidSudden);
+        }
+
+        for track in &self.tracks {
+            if track.groups.is_empty() || track.groups.iter().any(|x| x.segments.is_empty()) {
+                return Err(PrintError::EmptySlideTrack);
+            }
+            for segment in track.segments() {
+                let has_interim = segment.params().interim.is_some();
+                if has_interim != (segment.shape() == SlideSegmentShape::Angle) {
+                    return Err(PrintError::InvalidInterim(*segment));
+                }
+            }
+            for group in &track.groups {
+                group.dur.check_printable()?;
+            }
+            if normalize_slide_track(self.start.key, track).is_none() {
+                return Err(PrintError::InvalidSlideTrack(format!(
+                    "{}{}",
+                    self.start.key, track
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SlideDuration {
+    /// See [`RawInsn::check_printable`].
+    pub fn check_printable(&self) -> Result<(), PrintError> {
+        match self {
+            SlideDuration::Simple(dur) => dur.check_printable(),
+            SlideDuration::Custom(SlideStopTimeSpec::Bpm(bpm), dur) => {
+                check_value(*bpm, false)?;
+                match dur {
+                    Duration::Seconds(x) => check_value(*x, false),
+                    Duration::NumBeats(_) => Err(PrintError::BpmStopTimeWithBeats),
+                }
+            }
+            SlideDuration::Custom(SlideStopTimeSpec::Seconds(x), dur) => {
+                check_value(*x, true)?;
+                dur.check_printable()
+            }
+        }
+    }
+}
+
+impl Duration {
+    /// See [`RawInsn::check_printable`].
+    pub fn check_printable(&self) -> Result<(), PrintError> {
+        match self {
+            Duration::NumBeats(params) => {
+                if params.divisor == 0 {
+                    return Err(PrintError::ZeroDivisor);
+                }
+                if params.num < Rational::ZERO || super::note_ty::to_decimal(params.num).is_none() {
+                    return Err(PrintError::InvalidValue(params.num.to_f64()));
+                }
+                params.bpm.map_or(Ok(()), |x| check_value(x, false))
+            }
+            Duration::Seconds(x) => check_value(*x, true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::parse_maidata_insns;
+    use crate::WithSpan;
+    use proptest::prelude::*;
+
+    fn arb_value() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            8 => (1u32..100000).prop_map(|x| x as f64 / 16.0),
+            1 => Just(0.0),
+            1 => prop_oneof![
+                Just(-1.0),
+                Just(-0.0),
+                Just(f64::NAN),
+                Just(f64::INFINITY),
+                any::<f64>()
+            ],
+        ]
+    }
+
+    fn arb_key() -> impl Strategy<Value = Key> {
+        (0u8..8).prop_map(|x| Key::new(x).unwrap())
+    }
+
+    fn arb_sensor() -> impl Strategy<Value = TouchSensor> {
+        prop_oneof![
+            10 => (prop::sample::select(vec!['A', 'B', 'D', 'E']), 0u8..8)
+                .prop_map(|(group, index)| TouchSensor::new(group, Some(index)).unwrap()),
+            2 => Just(TouchSensor::new('C', None).unwrap()),
+            1 => Just(TouchSensor::new_unchecked('C', Some(0))),
+        ]
+    }
+
+    fn arb_shape() -> impl Strategy<Value = Option<TapShape>> {
+        prop::option::of(prop::sample::select(vec![
+            TapShape::Ring,
+            TapShape::Star,
+            TapShape::StarSpin,
+            TapShape::NoStar,
+            TapShape::Sudden,
+        ]))
+    }
+
+    fn arb_duration() -> impl Strategy<Value = Duration> {
+        prop_oneof![
+            (
+                prop::option::of(arb_value()),
+                0u32..64,
+                0i64..10000,
+                0u32..4
+            )
+                .prop_map(|(bpm, divisor, num, places)| {
+                    let num = Rational::new(num, 10i64.pow(places));
+                    Duration::NumBeats(NumBeatsParams { bpm, divisor, num })
+                }),
+            arb_value().prop_map(Duration::Seconds),
+        ]
+    }
+
+    fn arb_slide_duration() -> impl Strategy<Value = SlideDuration> {
+        let spec = prop_oneof![
+            arb_value().prop_map(SlideStopTimeSpec::Bpm),
+            arb_value().prop_map(SlideStopTimeSpec::Seconds),
+        ];
+        prop_oneof![
+            arb_duration().prop_map(SlideDuration::Simple),
+            (spec, arb_duration()).prop_map(|(spec, dur)| SlideDuration::Custom(spec, dur)),
+        ]
+    }
+
+    fn arb_segment() -> impl Strategy<Value = SlideSegment> {
+        let params = (arb_key(), prop::option::weighted(0.1, arb_key())).prop_map(
+            |(destination, interim)| SlideSegmentParams {
+                destination,
+                interim,
+            },
+        );
+        let constructors: Vec<fn(SlideSegmentParams) -> SlideSegment> = vec![
+            SlideSegment::Line,
+            SlideSegment::Arc,
+            SlideSegment::CircumferenceLeft,
+            SlideSegment::CircumferenceRight,
+            SlideSegment::V,
+            SlideSegment::P,
+            SlideSegment::Q,
+            SlideSegment::S,
+            SlideSegment::Z,
+            SlideSegment::Pp,
+            SlideSegment::Qq,
+            SlideSegment::Angle,
+            SlideSegment::Spread,
+        ];
+        (prop::sample::select(constructors), params, arb_key()).prop_map(|(f, mut params, key)| {
+            // mostly well-formed interim keys, so that enough cases get to the parser
+            let segment = f(params);
+            if segment.shape() == SlideSegmentShape::Angle {
+                params.interim = params.interim.or(Some(key));
+            } else {
+                params.interim = None;
+            }
+            f(params)
+        })
+    }
+
+    fn arb_track(is_sudden: bool) -> impl Strategy<Value = SlideTrack> {
+        let group = (
+            prop::collection::vec(arb_segment(), 0..4),
+            arb_slide_duration(),
+        )
+            .prop_map(|(segments, dur)| SlideSegmentGroup { segments, dur });
+        (prop::collection::vec(group, 0..3), any::<bool>()).prop_map(move |(groups, is_break)| {
+            SlideTrack {
+                groups,
+                modifier: SlideTrackModifier {
+                    is_break,
+                    is_sudden,
+                },
+            }
+        })
+    }
+
+    fn arb_note() -> impl Strategy<Value = RawNoteInsn> {
+        let tap_modifier = || {
+            (any::<bool>(), any::<bool>(), arb_shape()).prop_map(|(is_break, is_ex, shape)| {
+                TapModifier {
+                    is_break,
+                    is_ex,
+                    shape,
+                }
+            })
+        };
+        prop_oneof![
+            (arb_key(), tap_modifier())
+                .prop_map(|(key, modifier)| RawNoteInsn::Tap(TapParams { key, modifier })),
+            (arb_sensor(), any::<[bool; 3]>()).prop_map(
+                |(sensor, [is_break, is_ex, is_firework])| {
+                    RawNoteInsn::Touch(TouchParams {
+                        sensor,
+                        modifier: TouchModifier {
+                            is_break,
+                            is_ex,
+                            is_firework,
+                        },
+                    })
+                }
+            ),
+            (
+                arb_key(),
+                prop::option::of(arb_duration()),
+                any::<bool>(),
+                any::<bool>()
+            )
+                .prop_map(|(key, dur, is_break, is_ex)| {
+                    RawNoteInsn::Hold(HoldParams {
+                        key,
+                        dur,
+                        modifier: HoldModifier { is_break, is_ex },
+                    })
+                }),
+            (
+                arb_sensor(),
+                prop::option::of(arb_duration()),
+                any::<[bool; 3]>()
+            )
+                .prop_map(|(sensor, dur, [is_break, is_ex, is_firework])| {
+                    RawNoteInsn::TouchHold(TouchHoldParams {
+                        sensor,
+                        dur,
+                        modifier: TouchHoldModifier {
+                            is_break,
+                            is_ex,
+                            is_firework,
+                        },
+                    })
+                }),
+            (arb_key(), tap_modifier(), any::<bool>())
+                .prop_flat_map(|(key, modifier, is_sudden)| {
+                    (
+                        Just(TapParams { key, modifier }),
+                        prop::collection::vec(arb_track(is_sudden), 0..3),
+                    )
+                })
+                .prop_map(|(start, tracks)| RawNoteInsn::Slide(SlideParams { start, tracks })),
+        ]
+    }
+
+    fn arb_insn() -> impl Strategy<Value = RawInsn> {
+        let notes = || {
+            prop::collection::vec(
+                arb_note().prop_map(|x| x.with_span(crate::Span::empty_at(0, 1, 1))),
+                0..4,
+            )
+        };
+        prop_oneof![
+            1 => arb_value().prop_map(|new_bpm| RawInsn::Bpm(BpmParams { new_bpm })),
+            1 => (0u32..64).prop_map(|x| RawInsn::BeatDivisor(BeatDivisorParams::NewDivisor(x))),
+            1 => arb_value()
+                .prop_map(|x| RawInsn::BeatDivisor(BeatDivisorParams::NewAbsoluteDuration(x))),
+            1 => Just(RawInsn::Rest),
+            1 => Just(RawInsn::EndMark),
+            4 => notes().prop_map(RawInsn::Notes),
+            2 => prop::collection::vec(notes(), 0..4).prop_map(RawInsn::PseudoEach),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(2048))]
+
+        #[test]
+        fn test_round_trip(insn in arb_insn()) {
+            if insn.check_printable().is_ok() {
+                let text = insn.to_string();
+                let (parsed, state) = parse_maidata_insns(&text);
+                prop_assert!(!state.has_messages(), "{}: {:?}", text, state);
+                prop_assert_eq!(parsed.len(), 1, "{}", text);
+                prop_assert_eq!(&*parsed[0], &insn, "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unprintable() {
+        let tap = |shape| {
+            RawNoteInsn::Tap(TapParams {
+                key: Key::new(0).unwrap(),
+                modifier: TapModifier {
+                    shape,
+                    ..Default::default()
+                },
+            })
+            .with_span(crate::Span::empty_at(0, 1, 1))
+        };
+        assert_eq!(
+            RawInsn::Notes(vec![]).check_printable(),
+            Err(PrintError::EmptyBundle)
+        );
+        assert_eq!(
+            RawInsn::PseudoEach(vec![vec![tap(None)]]).check_printable(),
+            Err(PrintError::InvalidPseudoEach)
+        );
+        assert_eq!(
+            RawInsn::Notes(vec![tap(Some(TapShape::Ring))]).check_printable(),
+            Err(PrintError::InvalidShape(NoteType::Tap, TapShape::Ring))
+        );
+        assert_eq!(
+            RawInsn::Bpm(BpmParams { new_bpm: -0.0 }).check_printable(),
+            Err(PrintError::InvalidValue(-0.0))
+        );
+
+        // no decimal form for a third of a beat
+        let dur = Duration::NumBeats(NumBeatsParams {
+            bpm: None,
+            divisor: 4,
+            num: Rational::new(1, 3),
+        });
+        assert!(matches!(
+            dur.check_printable(),
+            Err(PrintError::InvalidValue(_))
+        ));
+
+        let (mut insns, _) = parse_maidata_insns("1V35[4:1],");
+        if let RawInsn::Notes(notes) = &mut *insns[0] {
+            if let RawNoteInsn::Slide(params) = &mut *notes[0] {
+                if let SlideSegment::Angle(x) = &mut params.tracks[0].groups[0].segments[0] {
+                    x.interim = None;
+                }
+            }
+        }
+        assert_eq!((*insns[0]).to_string(), "1V5[4:1],");
+        assert!(matches!(
+            insns[0].check_printable(),
+            Err(PrintError::InvalidInterim(_))
+        ));
+    }
+}
@@ -25,16 +25,19 @@ impl TryFrom<MaterializedSlideTrack> for FanSlide {
                 start: ((segment.start.index() + 7) % 8).try_into().unwrap(),
                 destination: segment.destination,
                 shape: NormalizedSlideSegmentShape::Fan,
+                ..segment
             },
             MaterializedSlideSegment {
                 start: segment.start,
                 destination: segment.destination,
                 shape: NormalizedSlideSegmentShape::Fan,
+                ..segment
             },
             MaterializedSlideSegment {
                 start: ((segment.start.index() + 1) % 8).try_into().unwrap(),
                 destination: segment.destination,
                 shape: NormalizedSlideSegmentShape::Fan,
+                ..segment
             },
         ]
         .iter()
@@ -81,4 +84,12 @@ impl JudgeNote for FanSlide {
             .map(|slide| slide.get_judge_result().unwrap())
             .max()
     }
+
+    fn get_score_weight(&self) -> u32 {
+        3
+    }
+
+    fn is_break(&self) -> bool {
+        self.sub_slides[0].is_break
+    }
 }
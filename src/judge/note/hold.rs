@@ -9,7 +9,7 @@ pub struct Hold {
     pub sensor: TouchSensor,
     pub appear_time: f64,
     pub tail_time: f64,
-    pub _is_break: bool,
+    pub is_break: bool,
     pub _is_ex: bool,
 
     head_judge_type: JudgeType,
@@ -27,7 +27,7 @@ impl From<MaterializedHold> for Hold {
             appear_time: m.ts,
             tail_time: m.ts + m.dur,
             sensor: key_to_sensor(m.key),
-            _is_break: m.is_break,
+            is_break: m.is_break,
             _is_ex: m.is_ex,
             head_judge_type: if m.is_ex {
                 JudgeType::ExTap
@@ -112,4 +112,12 @@ impl JudgeNote for Hold {
     fn get_judge_result(&self) -> Option<Timing> {
         self.result
     }
+
+    fn get_score_weight(&self) -> u32 {
+        2
+    }
+
+    fn is_break(&self) -> bool {
+        self.is_break
+    }
 }
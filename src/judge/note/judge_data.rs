@@ -2,6 +2,12 @@ use super::{JudgeParam, JudgeType, Timing};
 use enum_map::EnumMap;
 use lazy_static::lazy_static;
 
+/// Judge windows and score tables.
+///
+/// The scores are those of maimai DX's achievement rules, as tabulated on the community maimai DX
+/// wikis: a TAP is 500 at Critical and Perfect, 400 at any Great and 250 at Good; a break is 2500,
+/// 2000/1500/1250 for the three Greats and 1000 at Good, plus a bonus of 100% at Critical,
+/// 75%/50% for the two Perfects, 40% at Great and 30% at Good.
 #[derive(Clone, Debug)]
 pub struct JudgeData {
     judge_adjust_s: f64,
@@ -12,6 +18,12 @@ pub struct JudgeData {
     judge_param_table: EnumMap<JudgeType, JudgeParam>,
     hold_judge_percent: [i32; 5],
     hold_judge_param: [EnumMap<Timing, Timing>; 5],
+    /// Score of a non-break note weighing one TAP.
+    tap_score: EnumMap<Timing, u32>,
+    /// Score of a break note of any kind.
+    break_score: EnumMap<Timing, u32>,
+    /// Break bonus in percent of the maximum.
+    break_bonus_percent: EnumMap<Timing, u32>,
 }
 
 impl JudgeData {
@@ -92,6 +104,23 @@ impl JudgeData {
                     9f64,
                     f64::INFINITY,
                 ]),
+                JudgeParam::new([
+                    -9f64,
+                    -9f64,
+                    -9f64,
+                    -9f64,
+                    -9f64,
+                    -9f64,
+                    -9f64,
+                    18f64,
+                    18f64,
+                    18f64,
+                    18f64,
+                    18f64,
+                    18f64,
+                    18f64,
+                    f64::INFINITY,
+                ]),
             ]),
             hold_judge_percent: [0, 33, 67, 95, 100],
             hold_judge_param: [
@@ -181,6 +210,15 @@ impl JudgeData {
                     Timing::TooLate,
                 ]),
             ],
+            tap_score: EnumMap::from_array([
+                0, 250, 400, 400, 400, 500, 500, 500, 500, 500, 400, 400, 400, 250, 0,
+            ]),
+            break_score: EnumMap::from_array([
+                0, 1000, 1250, 1500, 2000, 2500, 2500, 2500, 2500, 2500, 2000, 1500, 1250, 1000, 0,
+            ]),
+            break_bonus_percent: EnumMap::from_array([
+                0, 30, 40, 40, 40, 50, 75, 100, 75, 50, 40, 40, 40, 30, 0,
+            ]),
         }
     }
 
@@ -243,6 +281,19 @@ impl JudgeData {
         }
         unreachable!();
     }
+
+    /// Score of a note weighing `weight` TAPs. Break notes score the same whatever their kind.
+    pub fn get_score(&self, timing: Timing, weight: u32, is_break: bool) -> u32 {
+        if is_break {
+            self.break_score[timing]
+        } else {
+            self.tap_score[timing] * weight
+        }
+    }
+
+    pub fn get_break_bonus_percent(&self, timing: Timing) -> u32 {
+        self.break_bonus_percent[timing]
+    }
 }
 
 lazy_static! {
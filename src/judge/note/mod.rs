@@ -50,6 +50,7 @@ pub enum JudgeType {
     Touch,
     Slide,
     ExTap,
+    ExTouch,
 }
 
 #[derive(Clone, Debug)]
@@ -141,6 +142,26 @@ pub trait JudgeNote {
     fn judge(&mut self, _getter: &TouchSensorStates, _current_time: f64);
 
     fn get_judge_result(&self) -> Option<Timing>;
+
+    /// How many TAPs the note is worth: 1 for taps and touches, 2 for holds, 3 for slides.
+    fn get_score_weight(&self) -> u32 {
+        1
+    }
+    fn is_break(&self) -> bool {
+        false
+    }
+    fn get_score(&self) -> Option<u32> {
+        self.get_judge_result()
+            .map(|x| JUDGE_DATA.get_score(x, self.get_score_weight(), self.is_break()))
+    }
+    /// `None` for non-break notes.
+    fn get_break_bonus_percent(&self) -> Option<u32> {
+        if !self.is_break() {
+            return None;
+        }
+        self.get_judge_result()
+            .map(|x| JUDGE_DATA.get_break_bonus_percent(x))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -239,4 +260,112 @@ impl JudgeNote for Note {
     fn get_judge_result(&self) -> Option<Timing> {
         self.get_impl().get_judge_result()
     }
+
+    fn get_score_weight(&self) -> u32 {
+        self.get_impl().get_score_weight()
+    }
+
+    fn is_break(&self) -> bool {
+        self.get_impl().is_break()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::parse_maidata_insns;
+    use crate::materialize::MaterializationContext;
+
+    fn judge_notes(source: &str) -> Vec<Note> {
+        let (insns, state) = parse_maidata_insns(source);
+        assert!(!state.has_messages(), "{:?}", state);
+        MaterializationContext::with_offset(0.0)
+            .materialize_insns(&insns)
+            .into_iter()
+            .filter(|x| !matches!(**x, MaterializedNote::Bpm(_)))
+            .map(|x| Note::try_from((*x).clone()).unwrap())
+            .collect()
+    }
+
+    /// Judge `note`, which is at 0s, by touching it `frames` after its time.
+    fn touch_at(note: &Note, frames: f64) -> Note {
+        let mut note = note.clone();
+        note.on_sensor(frames / FRAME_RATE);
+        note
+    }
+
+    #[test]
+    fn test_ex_touch() {
+        let notes = judge_notes("B1x,");
+        let touch = &notes[0];
+        for frames in [-8.5, -1.0, 0.0, 9.5, 17.5] {
+            let note = touch_at(touch, frames);
+            assert_eq!(
+                note.get_judge_result(),
+                Some(Timing::Critical),
+                "{}",
+                frames
+            );
+        }
+        assert_eq!(touch.get_end_time(), 18.0 / FRAME_RATE);
+        assert_eq!(
+            touch_at(touch, 18.5).get_judge_result(),
+            Some(Timing::TooLate)
+        );
+
+        // the same for the head of an EX touch hold
+        let notes = judge_notes("Chx[4:1],");
+        let Note::TouchHold(hold) = &notes[0] else {
+            unreachable!()
+        };
+        let mut hold = hold.clone();
+        hold.on_sensor(17.5 / FRAME_RATE);
+        assert_eq!(hold.head_result, Some(Timing::Critical));
+
+        // while a plain touch is only Critical up to 9 frames late
+        let notes = judge_notes("B1,");
+        assert_eq!(
+            touch_at(&notes[0], 9.5).get_judge_result(),
+            Some(Timing::LatePerfect)
+        );
+    }
+
+    #[test]
+    fn test_break_touch_score() {
+        let notes = judge_notes("B1b,B1,");
+        let (break_touch, touch) = (&notes[0], &notes[1]);
+        assert!(break_touch.is_break());
+        assert!(!touch.is_break());
+
+        let note = touch_at(break_touch, 0.0);
+        assert_eq!(note.get_score(), Some(2500));
+        assert_eq!(note.get_break_bonus_percent(), Some(100));
+        let note = touch_at(break_touch, 12.5);
+        assert_eq!(note.get_judge_result(), Some(Timing::LateGreat));
+        assert_eq!(note.get_score(), Some(2000));
+        assert_eq!(note.get_break_bonus_percent(), Some(40));
+
+        let note = touch_at(touch, 12.5);
+        assert_eq!(note.get_score(), Some(400));
+        assert_eq!(note.get_break_bonus_percent(), None);
+        assert_eq!(touch.get_score(), None);
+    }
+
+    #[test]
+    fn test_score_weight() {
+        let notes = judge_notes("1,B1,1h[4:1],Ch[4:1],1-5[4:1],1w5[4:1],");
+        let weights = notes
+            .iter()
+            .map(|x| x.get_score_weight())
+            .collect::<Vec<_>>();
+        // tap, touch, hold, touch hold, slide and fan slide
+        assert_eq!(weights, vec![1, 1, 2, 2, 3, 3]);
+        let critical = weights
+            .iter()
+            .map(|&x| JUDGE_DATA.get_score(Timing::Critical, x, false))
+            .collect::<Vec<_>>();
+        assert_eq!(critical, vec![500, 500, 1000, 1000, 1500, 1500]);
+        // a break is worth the same whatever its weight
+        assert_eq!(JUDGE_DATA.get_score(Timing::Critical, 3, true), 2500);
+    }
 }
@@ -12,7 +12,7 @@ pub struct Slide {
     pub path: Vec<Vec<TouchSensor>>,
     pub appear_time: f64,
     pub tail_time: f64,
-    pub _is_break: bool,
+    pub is_break: bool,
 
     judge_check_sensor_1: bool,
     judge_check_sensor_3: bool,
@@ -62,7 +62,7 @@ impl TryFrom<MaterializedSlideTrack> for Slide {
                 .ok_or("Slide path not found")?,
             appear_time: m.ts,
             tail_time: m.start_ts + dur,
-            _is_break: m.is_break,
+            is_break: m.is_break,
             judge_check_sensor_1: head_is_thunder && (1..=4).contains(&distance),
             judge_check_sensor_3: head_is_thunder && distance == 4,
             judge_type: JudgeType::Slide,
@@ -85,8 +85,8 @@ impl Slide {
                 .get_path_by_segment(&materialized_to_normalized_slide_segment(segment))
                 .ok_or("Slide path not found")?,
             appear_time: parent.ts,
-            tail_time: parent.start_ts + parent.dur,
-            _is_break: parent.is_break,
+            tail_time: segment.start_ts + segment.dur,
+            is_break: parent.is_break,
             judge_check_sensor_1: false,
             judge_check_sensor_3: false,
             judge_type: JudgeType::Slide,
@@ -195,6 +195,14 @@ impl JudgeNote for Slide {
     fn get_judge_result(&self) -> Option<Timing> {
         self.result
     }
+
+    fn get_score_weight(&self) -> u32 {
+        3
+    }
+
+    fn is_break(&self) -> bool {
+        self.is_break
+    }
 }
 
 fn materialized_to_normalized_slide_segment(
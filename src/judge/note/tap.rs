@@ -8,7 +8,7 @@ use crate::materialize::MaterializedTap;
 pub struct Tap {
     pub sensor: TouchSensor,
     pub appear_time: f64,
-    pub _is_break: bool,
+    pub is_break: bool,
     pub _is_ex: bool,
 
     judge_type: JudgeType,
@@ -21,7 +21,7 @@ impl From<MaterializedTap> for Tap {
         Self {
             sensor: key_to_sensor(m.key),
             appear_time: m.ts,
-            _is_break: m.is_break,
+            is_break: m.is_break,
             _is_ex: m.is_ex,
             judge_type: if m.is_ex {
                 JudgeType::ExTap
@@ -69,4 +69,8 @@ impl JudgeNote for Tap {
     fn get_judge_result(&self) -> Option<Timing> {
         self.result
     }
+
+    fn is_break(&self) -> bool {
+        self.is_break
+    }
 }
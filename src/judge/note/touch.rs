@@ -6,6 +6,8 @@ use crate::materialize::MaterializedTouch;
 pub struct Touch {
     pub sensor: TouchSensor,
     pub appear_time: f64,
+    pub is_break: bool,
+    pub _is_ex: bool,
 
     judge_type: JudgeType,
 
@@ -17,7 +19,13 @@ impl From<MaterializedTouch> for Touch {
         Self {
             sensor: m.sensor,
             appear_time: m.ts,
-            judge_type: JudgeType::Touch,
+            is_break: m.is_break,
+            _is_ex: m.is_ex,
+            judge_type: if m.is_ex {
+                JudgeType::ExTouch
+            } else {
+                JudgeType::Touch
+            },
             result: None,
         }
     }
@@ -59,4 +67,8 @@ impl JudgeNote for Touch {
     fn get_judge_result(&self) -> Option<Timing> {
         self.result
     }
+
+    fn is_break(&self) -> bool {
+        self.is_break
+    }
 }
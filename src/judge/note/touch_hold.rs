@@ -7,6 +7,8 @@ pub struct TouchHold {
     pub sensor: TouchSensor,
     pub appear_time: f64,
     pub tail_time: f64,
+    pub is_break: bool,
+    pub _is_ex: bool,
 
     head_judge_type: JudgeType,
     pub head_result: Option<Timing>,
@@ -23,7 +25,13 @@ impl From<MaterializedTouchHold> for TouchHold {
             appear_time: m.ts,
             tail_time: m.ts + m.dur,
             sensor: m.sensor,
-            head_judge_type: JudgeType::Touch,
+            is_break: m.is_break,
+            _is_ex: m.is_ex,
+            head_judge_type: if m.is_ex {
+                JudgeType::ExTouch
+            } else {
+                JudgeType::Touch
+            },
             head_result: None,
             prev_state: None,
             prev_time: None,
@@ -105,4 +113,12 @@ impl JudgeNote for TouchHold {
     fn get_judge_result(&self) -> Option<Timing> {
         self.result
     }
+
+    fn get_score_weight(&self) -> u32 {
+        2
+    }
+
+    fn is_break(&self) -> bool {
+        self.is_break
+    }
 }
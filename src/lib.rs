@@ -1,4 +1,5 @@
 pub mod container;
+pub mod cst;
 pub mod insn;
 pub mod judge;
 pub mod materialize;
@@ -35,6 +36,45 @@ pub enum Level {
     Plus(u8),
     /// The special "Lv.<any char>" form.
     Char(char),
+    /// The chart constant, e.g. `13.7`, in tenths (`137`).
+    Decimal(u16),
+}
+
+impl Level {
+    /// The tenth a "Lv.X+" level starts at.
+    const PLUS_TENTHS: u32 = 7;
+
+    /// The chart constant of the [`Decimal`](Self::Decimal) form.
+    pub fn constant(&self) -> Option<f64> {
+        match *self {
+            Level::Decimal(x) => Some(x as f64 / 10.0),
+            _ => None,
+        }
+    }
+
+    /// Position on the level scale in tenths, and a tie-breaker.
+    fn sort_key(&self) -> (u32, u32) {
+        match *self {
+            Level::Normal(lv) => (lv as u32 * 10, 0),
+            Level::Plus(lv) => (lv as u32 * 10 + Self::PLUS_TENTHS, 0),
+            Level::Decimal(x) => (x as u32, 1),
+            Level::Char(ch) => (u32::MAX, ch as u32),
+        }
+    }
+}
+
+/// Levels are ordered by where they start on the level scale, so `13` < `13.0` < `13.6` <
+/// `13+` < `13.7` < `14`. The "Lv.<any char>" forms come last.
+impl Ord for Level {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for Level {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl std::fmt::Display for Level {
@@ -44,6 +84,7 @@ impl std::fmt::Display for Level {
             Normal(lv) => write!(f, "{}", lv)?,
             Plus(lv) => write!(f, "{}+", lv)?,
             Char(lv) => write!(f, "{}", lv)?,
+            Decimal(x) => write!(f, "{}.{}", x / 10, x % 10)?,
         }
 
         Ok(())
@@ -1,28 +1,201 @@
 use std::vec;
 
-use super::Note;
+use super::timeline::BpmSegment;
+use super::{MusicalPosition, Note, Rational, TimingMap};
+use crate::container::ActiveMessage;
 use crate::materialize::{
-    MaterializedBpm, MaterializedHold, MaterializedSlideSegment, MaterializedSlideTrack,
-    MaterializedTap, MaterializedTapShape, MaterializedTouch, MaterializedTouchHold,
+    MaterializedActiveMessage, MaterializedBpm, MaterializedChart, MaterializedHold,
+    MaterializedSlideSegment, MaterializedSlideTrack, MaterializedTap, MaterializedTapShape,
+    MaterializedTouch, MaterializedTouchHold,
 };
 use crate::{insn, transform, Sp, WithSpan};
 
+/// Default interval between consecutive groups of a pseudo EACH, in seconds.
+pub const PSEUDO_EACH_INTERVAL: f64 = 0.001;
+
+/// Choices in materialization that the chart itself leaves open.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MaterializationOptions {
+    pub each_detection: EachDetection,
+    pub slide_star_each: SlideStarEach,
+    /// Shape of slide heads without a shape modifier.
+    pub default_slide_head_shape: MaterializedTapShape,
+    /// Interval between consecutive groups of a pseudo EACH (`` 1`2 ``), in seconds.
+    pub pseudo_each_interval: f64,
+    pub slide_wait_bpm: SlideWaitBpm,
+}
+
+impl Default for MaterializationOptions {
+    fn default() -> Self {
+        Self {
+            each_detection: EachDetection::default(),
+            slide_star_each: SlideStarEach::default(),
+            default_slide_head_shape: MaterializedTapShape::Star,
+            pseudo_each_interval: PSEUDO_EACH_INTERVAL,
+            slide_wait_bpm: SlideWaitBpm::default(),
+        }
+    }
+}
+
+/// Which notes are hit together, i.e. EACH.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum EachDetection {
+    /// Notes of the same `note/note/...` bundle, or of the same group of a pseudo EACH.
+    #[default]
+    Bundle,
+    /// Notes whose timestamps are at most `epsilon` seconds after the earliest of them, even
+    /// across bundles, e.g. `{#0}1,2,`. Only notes materialized by the same call are compared.
+    Timestamp { epsilon: f64 },
+}
+
+/// Which other notes make a slide star EACH.
+///
+/// Slide tracks are EACH when more than one of them start at once, regardless of this.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum SlideStarEach {
+    /// Any other note, touches included.
+    #[default]
+    AnyNote,
+    /// Taps, holds and other slide stars only.
+    KeyNotes,
+}
+
+/// Where the default wait of a slide, one beat before the star starts moving, takes its BPM
+/// from. Waits given in the slide duration, e.g. `[160#4:1]`, always win.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum SlideWaitBpm {
+    /// The BPM in effect where the slide starts, as in simai. Later BPM changes, even those
+    /// during the wait, don't matter.
+    #[default]
+    AtSlide,
+    /// A fixed BPM for the whole chart, e.g. its [`whole_bpm`](crate::container::Maidata::whole_bpm).
+    Fixed(f64),
+}
+
 pub struct MaterializationContext {
-    // TODO: is slides' default stop time really independent of BPM changes?
-    // currently it is dependent -- add a separate non-changing value (initialized by the "wholebpm"
-    // thing) to move to independent
-    curr_beat_dur: f64,
-    curr_note_dur: f64,
-    curr_ts: f64,
+    options: MaterializationOptions,
+    curr_note_len: NoteLength,
+    curr_pos: MusicalPosition,
+
+    // every BPM segment seen so far, starting with the one before the first BPM change
+    segments: Vec<BpmSegment>,
+    // timestamp of the end mark, after which nothing is materialized
+    end_ts: Option<f64>,
+    timing: TimingMap,
+}
+
+/// The length of one note as set by the last beat divisor.
+#[derive(Copy, Clone, Debug)]
+enum NoteLength {
+    Measures(Rational),
+    Seconds(f64),
 }
 
 impl MaterializationContext {
     pub fn with_offset(offset_secs: f64) -> Self {
+        let mut timing = TimingMap::default();
+        timing.push(offset_secs, Rational::ZERO, None, false);
         Self {
-            curr_beat_dur: 0.0,
-            curr_note_dur: 0.0,
-            curr_ts: offset_secs,
+            options: MaterializationOptions::default(),
+            curr_note_len: NoteLength::Measures(Rational::ZERO),
+            curr_pos: MusicalPosition::START,
+            segments: vec![BpmSegment {
+                start: MusicalPosition::START,
+                start_ts: offset_secs,
+                beat_dur: 0.0,
+            }],
+            end_ts: None,
+            timing,
+        }
+    }
+
+    pub fn options(mut self, options: MaterializationOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Resolve the positions of active messages into timestamps, using the BPM changes of the
+    /// instructions materialized so far.
+    ///
+    /// `offset_secs` takes the place of the chart offset given to [`with_offset`](Self::with_offset),
+    /// the chart's own offset applies if it's `None`.
+    fn materialize_active_messages(
+        &self,
+        messages: &[Sp<ActiveMessage>],
+        offset_secs: Option<f64>,
+    ) -> Vec<Sp<MaterializedActiveMessage>> {
+        let chart_offset = self.segments[0].start_ts;
+        let offset_secs = offset_secs.unwrap_or(chart_offset);
+        messages
+            .iter()
+            .map(|message| {
+                let ts = self.timing.ts_at_measure(message.measure) - chart_offset + offset_secs;
+                MaterializedActiveMessage {
+                    ts,
+                    content: message.content.value().to_owned(),
+                }
+                .with_span(message.span())
+            })
+            .collect()
+    }
+
+    /// Timestamp of a position reached by the instructions materialized so far.
+    fn pos_to_ts(&self, pos: MusicalPosition) -> f64 {
+        self.segments[pos.bpm_segment].ts(pos)
+    }
+
+    fn curr_beat_dur(&self) -> f64 {
+        self.segments[self.curr_pos.bpm_segment].beat_dur
+    }
+
+    /// Materialize a list of raw instructions into a chart, with the notes sorted by timestamp and
+    /// the BPM changes taken apart.
+    ///
+    /// The active messages are placed with the BPM changes of `insns`, offset by
+    /// `active_message_offset` (`amsg_first`) if any, or else by the chart offset.
+    pub fn materialize_chart<'a, I: IntoIterator<Item = &'a Sp<insn::RawInsn>>>(
+        &mut self,
+        insns: I,
+        active_messages: &[Sp<ActiveMessage>],
+        active_message_offset: Option<f64>,
+    ) -> MaterializedChart {
+        let mut notes = vec![];
+        let mut bpm_events = vec![];
+        for note in self.materialize_insns(insns) {
+            let span = note.span();
+            match note.into_inner() {
+                Note::Bpm(bpm) => bpm_events.push(bpm.with_span(span)),
+                note => notes.push(note.with_span(span)),
+            }
         }
+        // stable, so that notes at the same time stay in source order
+        notes.sort_by(|a, b| a.ts().total_cmp(&b.ts()));
+
+        let end_ts = self.end_ts.unwrap_or_else(|| {
+            notes
+                .iter()
+                .map(|x| x.end_ts())
+                .fold(self.pos_to_ts(self.curr_pos), f64::max)
+        });
+        let active_messages =
+            self.materialize_active_messages(active_messages, active_message_offset);
+        MaterializedChart {
+            notes,
+            bpm_events,
+            active_messages,
+            end_ts,
+            timing: self.timing.clone(),
+        }
+    }
+
+    /// Time and beat conversions for the instructions materialized so far.
+    pub fn timing_map(&self) -> &TimingMap {
+        &self.timing
+    }
+
+    /// Timestamp of the end mark, if one has been materialized.
+    pub fn end_ts(&self) -> Option<f64> {
+        self.end_ts
     }
 
     /// Materialize a list of raw instructions into notes.
@@ -30,33 +203,67 @@ impl MaterializationContext {
         &mut self,
         insns: I,
     ) -> Vec<Sp<Note>> {
-        insns
+        let mut notes = insns
             .into_iter()
             .flat_map(|insn| self.materialize_raw_insn(insn))
-            .collect()
+            .collect::<Vec<_>>();
+        if let EachDetection::Timestamp { epsilon } = self.options.each_detection {
+            self.mark_each_by_ts(&mut notes, epsilon);
+        }
+        notes
+    }
+
+    /// Mark notes EACH with those close enough in time, see [`EachDetection::Timestamp`].
+    fn mark_each_by_ts(&self, notes: &mut [Sp<Note>], epsilon: f64) {
+        let mut notes = notes.iter_mut().map(|x| &mut **x).collect::<Vec<_>>();
+        notes.sort_by(|a, b| a.ts().total_cmp(&b.ts()));
+        let mut rest = &mut notes[..];
+        while let Some(first) = rest.first() {
+            let start_ts = first.ts();
+            let n = rest
+                .iter()
+                .take_while(|x| x.ts() - start_ts <= epsilon)
+                .count();
+            let (group, tail) = std::mem::take(&mut rest).split_at_mut(n);
+            mark_each(group, self.options.slide_star_each);
+            rest = tail;
+        }
     }
 
     /// Read in one raw instruction and materialize into note(s) if applicable.
     fn materialize_raw_insn(&mut self, insn: &Sp<insn::RawInsn>) -> Vec<Sp<Note>> {
         use std::ops::Deref;
+        if self.end_ts.is_some() {
+            return vec![];
+        }
         match insn.deref() {
             insn::RawInsn::Bpm(params) => {
-                self.set_bpm(params.new_bpm);
+                let ts = self.pos_to_ts(self.curr_pos);
+                self.curr_pos.bpm_segment = self.segments.len();
+                self.segments.push(BpmSegment {
+                    start: self.curr_pos,
+                    start_ts: ts,
+                    beat_dur: bpm_to_beat_dur(params.new_bpm),
+                });
+                self.timing
+                    .push(ts, self.curr_pos.measure, Some(params.new_bpm), false);
                 vec![Note::Bpm(MaterializedBpm {
-                    ts: self.curr_ts,
+                    ts,
+                    pos: self.curr_pos,
                     bpm: params.new_bpm,
                 })
                 .with_span(insn.span())]
             }
             insn::RawInsn::BeatDivisor(params) => {
-                match params {
+                self.curr_note_len = match *params {
+                    // the parser rejects `{0}`
                     insn::BeatDivisorParams::NewDivisor(new_divisor) => {
-                        self.set_beat_divisor(*new_divisor);
+                        NoteLength::Measures(Rational::new(1, new_divisor.max(1) as i64))
                     }
                     insn::BeatDivisorParams::NewAbsoluteDuration(new_note_dur) => {
-                        self.curr_note_dur = *new_note_dur;
+                        NoteLength::Seconds(new_note_dur)
                     }
-                }
+                };
                 vec![]
             }
             insn::RawInsn::Rest => {
@@ -65,75 +272,226 @@ impl MaterializationContext {
                 vec![]
             }
             insn::RawInsn::EndMark => {
-                // TODO: make later materialize calls return error?
+                // the rest of the chart is ignored
+                self.end_ts = Some(self.pos_to_ts(self.curr_pos));
                 vec![]
             }
             insn::RawInsn::Notes(raw_notes) => {
-                let ts = self.advance_time();
-                let is_each = raw_notes.len() > 1;
-                let is_slide_each = raw_notes.iter().fold(0, |acc, x| {
-                    acc + match x.deref() {
-                        insn::RawNoteInsn::Slide(params) => params.tracks.len(),
-                        _ => 0,
-                    }
-                }) > 1;
-                raw_notes
+                let (ts, pos) = self.advance_time();
+                self.materialize_bundle(ts, pos, raw_notes, insn.span())
+            }
+            insn::RawInsn::PseudoEach(groups) => {
+                let (ts, pos) = self.advance_time();
+                groups
                     .iter()
-                    .flat_map(|raw_note| {
-                        self.materialize_raw_note(ts, raw_note, is_each, is_slide_each)
+                    .enumerate()
+                    .flat_map(|(i, raw_notes)| {
+                        // the groups share a musical position
+                        let ts = ts + self.options.pseudo_each_interval * i as f64;
+                        self.materialize_bundle(ts, pos, raw_notes, insn.span())
                     })
-                    .map(|note| note.with_span(insn.span()))
                     .collect()
             }
         }
     }
 
-    fn set_bpm(&mut self, new_bpm: f64) {
-        self.curr_beat_dur = bpm_to_beat_dur(new_bpm);
-    }
-
-    fn set_beat_divisor(&mut self, new_divisor: u32) {
-        self.curr_note_dur = divide_beat(self.curr_beat_dur, new_divisor);
+    /// Materialize notes sharing one timestamp, i.e. those of a `note/note/...` bundle.
+    fn materialize_bundle(
+        &self,
+        ts: f64,
+        pos: MusicalPosition,
+        raw_notes: &[Sp<insn::RawNoteInsn>],
+        span: crate::Span,
+    ) -> Vec<Sp<Note>> {
+        let mut notes = raw_notes
+            .iter()
+            .flat_map(|raw_note| self.materialize_raw_note(ts, pos, raw_note))
+            .collect::<Vec<_>>();
+        if self.options.each_detection == EachDetection::Bundle {
+            mark_each(
+                &mut notes.iter_mut().collect::<Vec<_>>(),
+                self.options.slide_star_each,
+            );
+        }
+        notes.into_iter().map(|note| note.with_span(span)).collect()
     }
 
-    /// Advances timestamp by one "note", return the timestamp before advancing (that of the
-    /// current note being materialized).
-    fn advance_time(&mut self) -> f64 {
-        let res = self.curr_ts;
-        self.curr_ts += self.curr_note_dur;
-        res
+    /// Advances position by one "note", return the timestamp and position before advancing (those
+    /// of the current note being materialized).
+    fn advance_time(&mut self) -> (f64, MusicalPosition) {
+        let pos = self.curr_pos;
+        let ts = self.pos_to_ts(pos);
+        let secs = match self.curr_note_len {
+            NoteLength::Measures(x) => match self.curr_pos.measure.checked_add(x) {
+                Some(measure) => {
+                    self.curr_pos.measure = measure;
+                    return (ts, pos);
+                }
+                // too fine a position to be kept exact, so the note is taken as time instead
+                None => x.to_f64() * 4.0 * self.curr_beat_dur(),
+            },
+            NoteLength::Seconds(x) => x,
+        };
+        self.curr_pos.secs += secs;
+        let bpm = self.timing.last_bpm();
+        self.timing.push(ts, pos.measure, bpm, true);
+        let end_ts = self.pos_to_ts(self.curr_pos);
+        self.timing.push(end_ts, pos.measure, bpm, false);
+        (ts, pos)
     }
 
     fn materialize_raw_note(
         &self,
         ts: f64,
+        pos: MusicalPosition,
         raw_note: &insn::RawNoteInsn,
-        is_each: bool,
-        is_slide_each: bool,
     ) -> Vec<Note> {
+        let beat_dur = self.curr_beat_dur();
         match raw_note {
             insn::RawNoteInsn::Tap(params) => {
-                let m_params = materialize_tap_params(ts, params, false, is_each);
+                let m_params = materialize_tap_params(ts, pos, params, MaterializedTapShape::Ring);
                 vec![Note::Tap(m_params)]
             }
             insn::RawNoteInsn::Touch(params) => {
-                let m_params = materialize_touch_params(ts, params, is_each);
+                let m_params = materialize_touch_params(ts, pos, params);
                 vec![Note::Touch(m_params)]
             }
-            insn::RawNoteInsn::Slide(params) => {
-                materialize_slide(ts, self.curr_beat_dur, params, is_each, is_slide_each)
-            }
+            insn::RawNoteInsn::Slide(params) => self.materialize_slide(ts, pos, params),
             insn::RawNoteInsn::Hold(params) => {
-                let m_params = materialize_hold_params(ts, self.curr_beat_dur, params, is_each);
+                let m_params = materialize_hold_params(ts, pos, beat_dur, params);
                 vec![Note::Hold(m_params)]
             }
             insn::RawNoteInsn::TouchHold(params) => {
-                let m_params =
-                    materialize_touch_hold_params(ts, self.curr_beat_dur, params, is_each);
+                let m_params = materialize_touch_hold_params(ts, pos, beat_dur, params);
                 vec![Note::TouchHold(m_params)]
             }
         }
     }
+
+    /// slide insn -> `vec![star tap, track, track, ...]`
+    fn materialize_slide(&self, ts: f64, pos: MusicalPosition, p: &insn::SlideParams) -> Vec<Note> {
+        let mut start_tap = Some(materialize_tap_params(
+            ts,
+            pos,
+            &p.start,
+            self.options.default_slide_head_shape,
+        ));
+        if p.tracks.is_empty() {
+            return vec![Note::Tap(start_tap.unwrap())];
+        }
+
+        p.tracks
+            .iter()
+            .map(|track| {
+                Note::SlideTrack(self.materialize_slide_track(
+                    ts,
+                    pos,
+                    p.start.key,
+                    start_tap.take(),
+                    track,
+                ))
+            })
+            .collect()
+    }
+
+    fn materialize_slide_track(
+        &self,
+        ts: f64,
+        pos: MusicalPosition,
+        mut start_key: insn::Key,
+        start_tap: Option<MaterializedTap>,
+        track: &insn::SlideTrack,
+    ) -> MaterializedSlideTrack {
+        let beat_dur = self.curr_beat_dur();
+        // in simai, stop time is actually encoded (overridden) in the duration spec of individual
+        // slide track
+        //
+        // take care of this, falling back to one beat as configured by `SlideWaitBpm`
+        //
+        // for chained slides only the first group's stop time matters, the star starts moving once
+        let default_stop_time = match self.options.slide_wait_bpm {
+            SlideWaitBpm::AtSlide => beat_dur,
+            SlideWaitBpm::Fixed(bpm) => bpm_to_beat_dur(bpm),
+        };
+        let stop_time = match track.groups.first().map(|x| x.dur) {
+            Some(insn::SlideDuration::Simple(duration)) => {
+                duration.bpm().map_or(default_stop_time, bpm_to_beat_dur)
+            }
+            Some(insn::SlideDuration::Custom(st, _)) => stop_time_spec_to_dur(st),
+            None => default_stop_time,
+        };
+
+        let start_ts = ts + stop_time;
+
+        // every group moves at its own speed, divided evenly among its segments
+        let mut dur = 0.0;
+        let mut segments = vec![];
+        for group in &track.groups {
+            let group_dur = materialize_duration(group.dur.slide_duration(), beat_dur);
+            let segment_dur = group_dur / group.segments.len() as f64;
+            for (i, segment) in group.segments.iter().enumerate() {
+                let segment_ts = start_ts + dur + segment_dur * i as f64;
+                segments.push(materialize_slide_segment(
+                    segment_ts,
+                    segment_dur,
+                    start_key,
+                    segment,
+                ));
+                start_key = segment.params().destination;
+            }
+            dur += group_dur;
+        }
+
+        MaterializedSlideTrack {
+            ts,
+            pos,
+            start_ts,
+            dur,
+            start_tap,
+            segments,
+            is_break: track.modifier.is_break,
+            is_sudden: track.modifier.is_sudden,
+            is_each: false,
+        }
+    }
+}
+
+/// Set the EACH flags of notes hit together.
+fn mark_each(notes: &mut [&mut Note], slide_star_each: SlideStarEach) {
+    // a slide is one note however many tracks it has
+    let is_head = |x: &Note| match x {
+        Note::Bpm(_) => false,
+        Note::SlideTrack(track) => track.start_tap.is_some(),
+        _ => true,
+    };
+    let is_key_head = |x: &Note| is_head(x) && !matches!(x, Note::Touch(_) | Note::TouchHold(_));
+    let heads = notes.iter().filter(|x| is_head(x)).count();
+    let key_heads = notes.iter().filter(|x| is_key_head(x)).count();
+    let tracks = notes
+        .iter()
+        .filter(|x| matches!(x, Note::SlideTrack(_)))
+        .count();
+
+    let is_each = heads > 1;
+    let is_star_each = match slide_star_each {
+        SlideStarEach::AnyNote => is_each,
+        SlideStarEach::KeyNotes => key_heads > 1,
+    };
+    for note in notes.iter_mut() {
+        match note {
+            Note::Bpm(_) => {}
+            Note::Tap(x) => x.is_each = is_each,
+            Note::Touch(x) => x.is_each = is_each,
+            Note::Hold(x) => x.is_each = is_each,
+            Note::TouchHold(x) => x.is_each = is_each,
+            Note::SlideTrack(x) => {
+                x.is_each = tracks > 1;
+                if let Some(start_tap) = &mut x.start_tap {
+                    start_tap.is_each = is_star_each;
+                }
+            }
+        }
+    }
 }
 
 fn bpm_to_beat_dur(bpm: f64) -> f64 {
@@ -146,133 +504,49 @@ fn divide_beat(beat_dur: f64, beat_divisor: u32) -> f64 {
 
 fn materialize_tap_params(
     ts: f64,
+    pos: MusicalPosition,
     p: &insn::TapParams,
-    is_slide_star: bool,
-    is_each: bool,
+    default_shape: MaterializedTapShape,
 ) -> MaterializedTap {
     let shape = match p.modifier.shape {
         Some(insn::TapShape::Ring) => MaterializedTapShape::Ring,
         Some(insn::TapShape::Star) => MaterializedTapShape::Star,
         Some(insn::TapShape::StarSpin) => MaterializedTapShape::StarSpin,
-        // TODO: handle invalid shape
-        Some(insn::TapShape::Invalid) => MaterializedTapShape::Invalid,
-        None => {
-            if is_slide_star {
-                MaterializedTapShape::Star
-            } else {
-                MaterializedTapShape::Ring
-            }
-        }
+        Some(insn::TapShape::NoStar) => MaterializedTapShape::NoStar,
+        Some(insn::TapShape::Sudden) => MaterializedTapShape::Sudden,
+        None => default_shape,
     };
 
     MaterializedTap {
         ts,
+        pos,
         key: p.key,
         shape,
         is_break: p.modifier.is_break,
         is_ex: p.modifier.is_ex,
-        is_each,
+        is_each: false,
     }
 }
 
-fn materialize_touch_params(ts: f64, p: &insn::TouchParams, is_each: bool) -> MaterializedTouch {
+fn materialize_touch_params(
+    ts: f64,
+    pos: MusicalPosition,
+    p: &insn::TouchParams,
+) -> MaterializedTouch {
     MaterializedTouch {
         ts,
+        pos,
         sensor: p.sensor,
-        is_each,
-    }
-}
-
-/// slide insn -> `vec![star tap, track, track, ...]`
-fn materialize_slide(
-    ts: f64,
-    beat_dur: f64,
-    p: &insn::SlideParams,
-    is_each: bool,
-    is_slide_each: bool,
-) -> Vec<Note> {
-    let mut start_tap = Some(materialize_tap_params(ts, &p.start, true, is_each));
-    if p.tracks.is_empty() {
-        return vec![Note::Tap(start_tap.unwrap())];
-    }
-
-    p.tracks.iter().map(|track| {
-        Note::SlideTrack(materialize_slide_track(
-            ts,
-            beat_dur,
-            p.start.key,
-            start_tap.take(),
-            track,
-            is_slide_each,
-        ))
-    }).collect()
-}
-
-fn materialize_slide_track(
-    ts: f64,
-    beat_dur: f64,
-    mut start_key: insn::Key,
-    start_tap: Option<MaterializedTap>,
-    track: &insn::SlideTrack,
-    is_each: bool,
-) -> MaterializedSlideTrack {
-    // in simai, stop time is actually encoded (overridden) in the duration spec of individual
-    // slide track
-    //
-    // take care of this, falling back to beat duration of current bpm
-    let stop_time = match track.dur {
-        insn::SlideDuration::Simple(duration) => duration.bpm().map_or(beat_dur, bpm_to_beat_dur),
-        insn::SlideDuration::Custom(st, _) => stop_time_spec_to_dur(st),
-    };
-
-    let start_ts = ts + stop_time;
-
-    let segments = track
-        .segments
-        .iter()
-        .map(|segment| {
-            let result = materialize_slide_segment(start_key, segment);
-            start_key = segment.params().destination;
-            result
-        })
-        .collect();
-
-    MaterializedSlideTrack {
-        ts,
-        start_ts,
-        dur: materialize_duration(track.dur.slide_duration(), beat_dur),
-        start_tap,
-        segments,
-        is_break: track.modifier.is_break,
-        is_sudden: track.modifier.is_sudden,
-        is_each,
+        is_break: p.modifier.is_break,
+        is_ex: p.modifier.is_ex,
+        is_firework: p.modifier.is_firework,
+        is_each: false,
     }
 }
 
-// fn materialize_slide_segment_group(
-//     beat_dur: f64,
-//     start: insn::Key,
-//     group: &insn::SlideSegmentGroup,
-// ) -> MaterializedSlideSegmentGroup {
-//     let mut start = start;
-//     let segments = group
-//         .segments
-//         .iter()
-//         .map(|segment| {
-//             let result = materialize_slide_segment(start, segment);
-//             // TODO: trait for slide end position
-//             start = segment.params().destination;
-//             result
-//         })
-//         .collect();
-
-//     MaterializedSlideSegmentGroup {
-//         dur: materialize_duration(group.dur.slide_duration(), beat_dur),
-//         segments,
-//     }
-// }
-
 fn materialize_slide_segment(
+    start_ts: f64,
+    dur: f64,
     start: insn::Key,
     segment: &insn::SlideSegment,
 ) -> MaterializedSlideSegment {
@@ -282,6 +556,8 @@ fn materialize_slide_segment(
     let params = segment.params();
 
     MaterializedSlideSegment {
+        start_ts,
+        dur,
         start: params.start,
         destination: params.destination,
         shape,
@@ -290,31 +566,38 @@ fn materialize_slide_segment(
 
 fn materialize_hold_params(
     ts: f64,
+    pos: MusicalPosition,
     beat_dur: f64,
     p: &insn::HoldParams,
-    is_each: bool,
 ) -> MaterializedHold {
     MaterializedHold {
         ts,
-        dur: materialize_duration(p.dur, beat_dur),
+        pos,
+        // the no-duration form is a zero-length hold
+        dur: p.dur.map_or(0.0, |dur| materialize_duration(dur, beat_dur)),
         key: p.key,
         is_break: p.modifier.is_break,
         is_ex: p.modifier.is_ex,
-        is_each,
+        is_each: false,
     }
 }
 
 fn materialize_touch_hold_params(
     ts: f64,
+    pos: MusicalPosition,
     beat_dur: f64,
     p: &insn::TouchHoldParams,
-    is_each: bool,
 ) -> MaterializedTouchHold {
     MaterializedTouchHold {
         ts,
-        dur: materialize_duration(p.dur, beat_dur),
+        pos,
+        // the no-duration form is a zero-length hold
+        dur: p.dur.map_or(0.0, |dur| materialize_duration(dur, beat_dur)),
         sensor: p.sensor,
-        is_each,
+        is_break: p.modifier.is_break,
+        is_ex: p.modifier.is_ex,
+        is_firework: p.modifier.is_firework,
+        is_each: false,
     }
 }
 
@@ -322,7 +605,7 @@ fn materialize_duration(x: insn::Duration, beat_dur: f64) -> f64 {
     match x {
         insn::Duration::NumBeats(p) => {
             let beat_dur = p.bpm.map_or(beat_dur, bpm_to_beat_dur);
-            divide_beat(beat_dur, p.divisor) * (p.num as f64)
+            divide_beat(beat_dur, p.divisor) * p.num.to_f64()
         }
         insn::Duration::Seconds(x) => x,
     }
@@ -334,3 +617,287 @@ fn stop_time_spec_to_dur(x: insn::SlideStopTimeSpec) -> f64 {
         insn::SlideStopTimeSpec::Seconds(x) => x,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::parse_maidata_insns;
+
+    #[test]
+    fn test_chained_slide_timing() {
+        // one beat is 0.5s
+        let (insns, state) = parse_maidata_insns("(120){4}1-4[8:1]q7[8:3],");
+        assert!(!state.has_messages(), "{:?}", state);
+        let notes = MaterializationContext::with_offset(0.0).materialize_insns(&insns);
+        let track = notes
+            .iter()
+            .find_map(|x| match &**x {
+                Note::SlideTrack(track) => Some(track.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(track.start_ts, 0.5);
+        assert_eq!(track.dur, 1.0);
+        let timing = track
+            .segments
+            .iter()
+            .map(|x| (x.start_ts, x.dur))
+            .collect::<Vec<_>>();
+        assert_eq!(timing, vec![(0.5, 0.25), (0.75, 0.75)]);
+
+        // segments sharing a duration split it evenly
+        let (insns, _) = parse_maidata_insns("(120){4}1-4q7[2:1],");
+        let notes = MaterializationContext::with_offset(0.0).materialize_insns(&insns);
+        let Note::SlideTrack(track) = &*notes[notes.len() - 1] else {
+            unreachable!()
+        };
+        assert_eq!(track.segments[1].start_ts, 1.0);
+        assert_eq!(track.segments[1].dur, 0.5);
+
+        // fractional beat counts, an eighth is 0.25s
+        let (insns, _) = parse_maidata_insns("(120){4}1-4[8:1.5]q7[8:0.5],");
+        let notes = MaterializationContext::with_offset(0.0).materialize_insns(&insns);
+        let Note::SlideTrack(track) = &*notes[notes.len() - 1] else {
+            unreachable!()
+        };
+        assert_eq!(track.dur, 0.5);
+        let timing = track
+            .segments
+            .iter()
+            .map(|x| (x.start_ts, x.dur))
+            .collect::<Vec<_>>();
+        assert_eq!(timing, vec![(0.5, 0.375), (0.875, 0.125)]);
+    }
+
+    #[test]
+    fn test_slide_wait() {
+        let first_track = |mut mcx: MaterializationContext, src: &str| {
+            let (insns, state) = parse_maidata_insns(src);
+            assert!(!state.has_messages(), "{:?}", state);
+            mcx.materialize_chart(&insns, &[], None)
+                .notes
+                .iter()
+                .find_map(|x| match &**x {
+                    Note::SlideTrack(track) => Some(track.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // by default the wait is one beat at the BPM in effect where the slide starts
+        let src = "(120){4}1,(60)1-5[4:1],";
+        let track = first_track(MaterializationContext::with_offset(0.0), src);
+        assert_eq!(track.ts, 0.5);
+        assert_eq!(track.start_ts, 1.5);
+        assert_eq!(track.dur, 1.0);
+
+        // later BPM changes, even those during the wait, don't affect it
+        let src = "(120){4}1-5[4:1],(60)1,";
+        let track = first_track(MaterializationContext::with_offset(0.0), src);
+        assert_eq!(track.start_ts, 0.5);
+
+        // a fixed wait BPM, e.g. the `wholebpm`, replaces it but not waits given in the duration
+        let mcx = || {
+            MaterializationContext::with_offset(0.0).options(MaterializationOptions {
+                slide_wait_bpm: SlideWaitBpm::Fixed(240.0),
+                ..Default::default()
+            })
+        };
+        let track = first_track(mcx(), "(120){4}1,(60)1-5[4:1],");
+        assert_eq!(track.start_ts, 0.75);
+        assert_eq!(track.dur, 1.0);
+        let track = first_track(mcx(), "(120){4}1,(60)1-5[160#4:1],");
+        assert_eq!(track.start_ts, 0.875);
+        let track = first_track(mcx(), "(120){4}1,(60)1-5[0.5##1],");
+        assert_eq!(track.start_ts, 1.0);
+    }
+
+    #[test]
+    fn test_each() {
+        let each_flags = |options: MaterializationOptions, src: &str| {
+            let (insns, state) = parse_maidata_insns(src);
+            assert!(!state.has_messages(), "{:?}", state);
+            MaterializationContext::with_offset(0.0)
+                .options(options)
+                .materialize_insns(&insns)
+                .iter()
+                .filter_map(|x| match &**x {
+                    Note::Tap(tap) => Some(tap.is_each),
+                    Note::Touch(touch) => Some(touch.is_each),
+                    Note::SlideTrack(track) => {
+                        Some(track.start_tap.map_or(track.is_each, |x| x.is_each))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let by_ts = MaterializationOptions {
+            each_detection: EachDetection::Timestamp { epsilon: 0.00005 },
+            ..Default::default()
+        };
+
+        // close enough in time, different bundles
+        let src = "(120){#0.00003}1,2,3,";
+        assert_eq!(
+            each_flags(Default::default(), src),
+            vec![false, false, false]
+        );
+        assert_eq!(each_flags(by_ts, src), vec![true, true, false]);
+
+        // pseudo EACH groups are further apart than the epsilon
+        assert_eq!(each_flags(by_ts, "(120)1/2`3,"), vec![true, true, false]);
+        let options = MaterializationOptions {
+            pseudo_each_interval: 0.00004,
+            ..by_ts
+        };
+        assert_eq!(each_flags(options, "(120)1/2`3,"), vec![true, true, true]);
+
+        // slide stars: one slide with two tracks is not EACH, the tracks are
+        let src = "(120)1-5[4:1]*-4[4:1],";
+        assert_eq!(each_flags(Default::default(), src), vec![false, true]);
+        let src = "(120)1-5[4:1]/C,";
+        assert_eq!(each_flags(Default::default(), src), vec![true, true]);
+        let options = MaterializationOptions {
+            slide_star_each: SlideStarEach::KeyNotes,
+            ..Default::default()
+        };
+        assert_eq!(each_flags(options, src), vec![false, true]);
+        assert_eq!(each_flags(options, "(120)1-5[4:1]/2,"), vec![true, true]);
+    }
+
+    #[test]
+    fn test_tap_shape() {
+        let (insns, _) = parse_maidata_insns("(120)1,1$,1-5[4:1],1@-5[4:1],1?-5[4:1],1!-5[4:1],");
+        let shapes = |mut mcx: MaterializationContext| {
+            mcx.materialize_insns(&insns)
+                .iter()
+                .filter_map(|x| match &**x {
+                    Note::Tap(tap) => Some(tap.shape),
+                    Note::SlideTrack(track) => track.start_tap.map(|x| x.shape),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            shapes(MaterializationContext::with_offset(0.0)),
+            vec![
+                MaterializedTapShape::Ring,
+                MaterializedTapShape::Star,
+                MaterializedTapShape::Star,
+                MaterializedTapShape::Ring,
+                MaterializedTapShape::NoStar,
+                MaterializedTapShape::Sudden,
+            ]
+        );
+
+        // only slide heads without a shape take the default
+        let options = MaterializationOptions {
+            default_slide_head_shape: MaterializedTapShape::StarSpin,
+            ..Default::default()
+        };
+        let mcx = MaterializationContext::with_offset(0.0).options(options);
+        assert_eq!(
+            shapes(mcx)[..4],
+            [
+                MaterializedTapShape::Ring,
+                MaterializedTapShape::Star,
+                MaterializedTapShape::StarSpin,
+                MaterializedTapShape::Ring,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_touch_firework() {
+        let (insns, _) = parse_maidata_insns("(120)Cf/B1,C1hf[4:1]/E2h[4:1],");
+        let notes = MaterializationContext::with_offset(0.0).materialize_insns(&insns);
+        let fireworks = notes
+            .iter()
+            .filter_map(|x| match &**x {
+                Note::Touch(touch) => Some(touch.is_firework),
+                Note::TouchHold(hold) => Some(hold.is_firework),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(fireworks, vec![true, false, true, false]);
+
+        let json = serde_json::to_value(&*notes[1]).unwrap();
+        assert_eq!(json["type"], "touch");
+        assert_eq!(json["is_firework"], true);
+    }
+
+    #[test]
+    fn test_musical_position() {
+        // 3000 notes of a third of a measure, then a BPM change
+        let source = format!("(150){{3}}{}(300)1,{{#0.25}}2,3,", "1,".repeat(3000));
+        let (insns, _) = parse_maidata_insns(&source);
+        let notes = MaterializationContext::with_offset(1.0).materialize_insns(&insns);
+        let positions = notes
+            .iter()
+            .filter_map(|x| match &**x {
+                Note::Tap(tap) => Some((tap.ts, tap.pos)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // up to the rounding of the single conversion to seconds
+        let assert_ts = |ts: f64, expected: f64| assert!((ts - expected).abs() < 1e-9, "{}", ts);
+
+        // one measure at BPM 150 is 1.6s, and the last one of the 3000 notes is still on the grid
+        let (ts, pos) = positions[2999];
+        assert_eq!(pos.measure, Rational::new(2999, 3));
+        assert_eq!(pos.bpm_segment, 1);
+        assert_ts(ts, 1.0 + 1.6 * 2999.0 / 3.0);
+
+        // the divisor carries over the BPM change, the BPM note starts the next segment
+        let Note::Bpm(bpm) = &*notes[notes.len() - 4] else {
+            unreachable!()
+        };
+        assert_eq!(bpm.pos.measure, Rational::from(1000));
+        assert_eq!(bpm.pos.bpm_segment, 2);
+        assert_ts(bpm.ts, 1601.0);
+        let (ts, pos) = positions[3001];
+        assert_eq!(pos.measure, Rational::new(3001, 3));
+        assert_ts(ts, 1601.0 + 0.8 / 3.0);
+
+        // absolute durations are kept apart from the musical position
+        let (ts, pos) = positions[3002];
+        assert_eq!(pos.measure, Rational::new(3001, 3));
+        assert_eq!(pos.secs, 0.25);
+        assert_ts(ts, 1601.0 + 0.8 / 3.0 + 0.25);
+        assert_eq!(pos.to_string(), "measure 3001/3 + 0.25s");
+    }
+
+    #[test]
+    fn test_musical_position_overflow() {
+        // the sum of these measures doesn't fit in an `i64` fraction from the fourth one on
+        let source = "(120){999983}1,{999979}1,{999961}1,{999959}1,{999953}1,1,";
+        let (insns, _) = parse_maidata_insns(source);
+        let notes = MaterializationContext::with_offset(0.0).materialize_insns(&insns);
+        let positions = notes
+            .iter()
+            .filter_map(|x| match &**x {
+                Note::Tap(tap) => Some((tap.ts, tap.pos)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(positions.len(), 6);
+
+        // one measure at BPM 120 is 2s
+        let mut expected = 0.0;
+        for (i, divisor) in [999983.0, 999979.0, 999961.0, 999959.0, 999953.0, 999953.0]
+            .into_iter()
+            .enumerate()
+        {
+            let (ts, _) = positions[i];
+            assert!((ts - expected).abs() < 1e-12, "{}: {}", i, ts);
+            expected += 2.0 / divisor;
+        }
+
+        // the position stays exact as long as it can, and goes on in seconds from there
+        let (_, pos) = positions[3];
+        assert_eq!(pos.secs, 0.0);
+        let (_, pos) = positions[4];
+        assert_eq!(pos.measure, positions[3].1.measure);
+        assert!((pos.secs - 2.0 / 999959.0).abs() < 1e-15);
+    }
+}
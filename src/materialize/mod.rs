@@ -1,9 +1,12 @@
 mod context;
+mod timeline;
 
 pub use context::*;
+pub use timeline::{MusicalPosition, Rational, TimingMap};
 
 use crate::insn::{Key, TouchSensor};
 use crate::transform::NormalizedSlideSegmentShape;
+use crate::Sp;
 use serde::{Deserialize, Serialize};
 
 pub type TimestampInSeconds = f64;
@@ -21,15 +24,60 @@ pub enum Note {
     SlideTrack(MaterializedSlideTrack),
 }
 
+impl Note {
+    pub fn ts(&self) -> TimestampInSeconds {
+        match self {
+            Note::Bpm(x) => x.ts,
+            Note::Tap(x) => x.ts,
+            Note::Touch(x) => x.ts,
+            Note::Hold(x) => x.ts,
+            Note::TouchHold(x) => x.ts,
+            Note::SlideTrack(x) => x.ts,
+        }
+    }
+
+    /// When the note is over, e.g. the release of a hold or the star reaching the end of a slide.
+    pub fn end_ts(&self) -> TimestampInSeconds {
+        match self {
+            Note::Hold(x) => x.ts + x.dur,
+            Note::TouchHold(x) => x.ts + x.dur,
+            Note::SlideTrack(x) => x.start_ts + x.dur,
+            _ => self.ts(),
+        }
+    }
+}
+
+/// The materialized notes of one chart, see
+/// [`AssociatedBeatmapData::materialize`](crate::container::AssociatedBeatmapData::materialize).
+#[derive(Clone, Debug)]
+pub struct MaterializedChart {
+    /// All notes but BPM changes, sorted by timestamp.
+    pub notes: Vec<Sp<Note>>,
+    pub bpm_events: Vec<Sp<MaterializedBpm>>,
+    /// Sorted like the `amsg_time` list, which needn't be in time order.
+    pub active_messages: Vec<Sp<MaterializedActiveMessage>>,
+    /// The timestamp of the `E` end mark, or else when the last note or rest is over.
+    pub end_ts: TimestampInSeconds,
+    pub timing: TimingMap,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedBpm {
     pub ts: TimestampInSeconds,
+    pub pos: MusicalPosition,
     pub bpm: f64,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MaterializedActiveMessage {
+    pub ts: TimestampInSeconds,
+    pub content: String,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedTap {
     pub ts: TimestampInSeconds,
+    pub pos: MusicalPosition,
     pub key: Key,
     pub shape: MaterializedTapShape,
     pub is_break: bool,
@@ -43,19 +91,28 @@ pub enum MaterializedTapShape {
     Ring,
     Star,
     StarSpin,
-    Invalid,
+    /// Slide head marked `?`: the star is not shown and the slide starts on its own.
+    NoStar,
+    /// Slide head marked `!`: like [`NoStar`](Self::NoStar), and the slide appears without
+    /// fading in.
+    Sudden,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedTouch {
     pub ts: TimestampInSeconds,
+    pub pos: MusicalPosition,
     pub sensor: TouchSensor,
+    pub is_break: bool,
+    pub is_ex: bool,
+    pub is_firework: bool,
     pub is_each: bool,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedHold {
     pub ts: TimestampInSeconds,
+    pub pos: MusicalPosition,
     pub dur: DurationInSeconds,
     pub key: Key,
     pub is_break: bool,
@@ -66,14 +123,19 @@ pub struct MaterializedHold {
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedTouchHold {
     pub ts: TimestampInSeconds,
+    pub pos: MusicalPosition,
     pub dur: DurationInSeconds,
     pub sensor: TouchSensor,
+    pub is_break: bool,
+    pub is_ex: bool,
+    pub is_firework: bool,
     pub is_each: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedSlideTrack {
     pub ts: TimestampInSeconds,
+    pub pos: MusicalPosition,
     pub start_ts: TimestampInSeconds,
     pub dur: DurationInSeconds,
     pub start_tap: Option<MaterializedTap>,
@@ -85,6 +147,9 @@ pub struct MaterializedSlideTrack {
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MaterializedSlideSegment {
+    /// When the star starts moving along this segment.
+    pub start_ts: TimestampInSeconds,
+    pub dur: DurationInSeconds,
     pub start: Key,
     pub destination: Key,
     pub shape: NormalizedSlideSegmentShape,
@@ -0,0 +1,428 @@
+//! Exact musical positions of notes, kept apart from their timestamps in seconds.
+
+use serde::{Deserialize, Serialize};
+
+/// A fraction in lowest terms with a positive denominator.
+///
+/// The arithmetic operators panic if the result doesn't fit in `i64`; the `checked_*` methods
+/// return `None` instead.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+impl Rational {
+    pub const ZERO: Rational = Rational { num: 0, den: 1 };
+
+    /// Panics if `den` is zero.
+    pub fn new(num: i64, den: i64) -> Self {
+        assert!(den != 0, "zero denominator");
+        Self::reduce(num.into(), den.into()).expect("rational overflow")
+    }
+
+    /// `num / den` in lowest terms, `None` if it doesn't fit.
+    fn reduce(num: i128, den: i128) -> Option<Self> {
+        let g = gcd(num.unsigned_abs(), den.unsigned_abs()).max(1) as i128;
+        let sign = den.signum();
+        Some(Self {
+            num: i64::try_from(sign * num / g).ok()?,
+            den: i64::try_from(sign * den / g).ok()?,
+        })
+    }
+
+    pub fn num(&self) -> i64 {
+        self.num
+    }
+
+    pub fn den(&self) -> i64 {
+        self.den
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// The largest integer not greater than the fraction.
+    pub fn floor(&self) -> i64 {
+        self.num.div_euclid(self.den)
+    }
+
+    pub fn checked_add(self, rhs: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduce(a * d + c * b, b * d)
+    }
+
+    pub fn checked_sub(self, rhs: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduce(a * d - c * b, b * d)
+    }
+
+    pub fn checked_mul(self, rhs: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduce(a * c, b * d)
+    }
+
+    /// Both operands widened, so that products of two parts and their sums don't overflow.
+    fn wide(self, rhs: Rational) -> (i128, i128, i128, i128) {
+        (
+            self.num.into(),
+            self.den.into(),
+            rhs.num.into(),
+            rhs.den.into(),
+        )
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(x: i64) -> Self {
+        Self { num: x, den: 1 }
+    }
+}
+
+impl std::ops::Add for Rational {
+    type Output = Rational;
+
+    fn add(self, rhs: Rational) -> Rational {
+        self.checked_add(rhs).expect("rational overflow")
+    }
+}
+
+impl std::ops::AddAssign for Rational {
+    fn add_assign(&mut self, rhs: Rational) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::ops::Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, rhs: Rational) -> Rational {
+        self.checked_mul(rhs).expect("rational overflow")
+    }
+}
+
+impl std::ops::Sub for Rational {
+    type Output = Rational;
+
+    fn sub(self, rhs: Rational) -> Rational {
+        self.checked_sub(rhs).expect("rational overflow")
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let t = b;
+        b = a % b;
+        a = t;
+    }
+    a
+}
+
+/// Where a note is in the chart, in musical terms.
+///
+/// Positions advance by exact fractions of a measure for `{N}` divisors. `{#x}` divisors have no
+/// musical length, so the time they take is kept apart in `secs`.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MusicalPosition {
+    /// Measures of 4 beats since the start of the chart.
+    pub measure: Rational,
+    /// Seconds taken by `{#x}` divisors since the start of the chart.
+    pub secs: f64,
+    /// Index of the BPM segment the position is in, i.e. the number of BPM changes before it,
+    /// counting the one at the position itself.
+    pub bpm_segment: usize,
+}
+
+impl MusicalPosition {
+    pub const START: MusicalPosition = MusicalPosition {
+        measure: Rational::ZERO,
+        secs: 0.0,
+        bpm_segment: 0,
+    };
+
+    /// Beats since the start of the chart, not counting `secs`. `None` if it doesn't fit.
+    pub fn beat(&self) -> Option<Rational> {
+        self.measure.checked_mul(Rational::from(4))
+    }
+}
+
+impl std::fmt::Display for MusicalPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "measure {}", self.measure)?;
+        if self.secs != 0.0 {
+            write!(f, " + {}s", self.secs)?;
+        }
+        Ok(())
+    }
+}
+
+/// A stretch of the chart with a constant BPM.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct BpmSegment {
+    pub start: MusicalPosition,
+    pub start_ts: f64,
+    /// Zero before the first BPM change.
+    pub beat_dur: f64,
+}
+
+impl BpmSegment {
+    /// Timestamp of a position in this segment.
+    pub fn ts(&self, pos: MusicalPosition) -> f64 {
+        let measures = match pos.measure.checked_sub(self.start.measure) {
+            Some(x) => x.to_f64(),
+            None => pos.measure.to_f64() - self.start.measure.to_f64(),
+        };
+        self.start_ts + measures * 4.0 * self.beat_dur + (pos.secs - self.start.secs)
+    }
+}
+
+/// Conversions between timestamps and musical time, e.g. for seek bars and metronomes.
+///
+/// Beats and measures are counted from the start of the chart like [`MusicalPosition::measure`],
+/// but as `f64` as they are mostly needed between notes. The map is built while materializing,
+/// see [`MaterializationContext::timing_map`](super::MaterializationContext::timing_map).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TimingMap {
+    /// Points where the relation between time and measures changes, in order.
+    anchors: Vec<TimingAnchor>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+struct TimingAnchor {
+    ts: f64,
+    measure: Rational,
+    /// `None` before the first BPM change.
+    bpm: Option<f64>,
+    /// Time passes without measures, during notes of `{#x}` divisors.
+    is_paused: bool,
+}
+
+impl TimingAnchor {
+    fn measure_dur(&self) -> f64 {
+        match self.bpm {
+            Some(bpm) if !self.is_paused => 240.0 / bpm,
+            _ => 0.0,
+        }
+    }
+}
+
+impl TimingMap {
+    pub(crate) fn push(&mut self, ts: f64, measure: Rational, bpm: Option<f64>, is_paused: bool) {
+        // consecutive pauses are one
+        if let Some(last) = self.anchors.last() {
+            if is_paused && !last.is_paused && last.ts == ts && self.anchors.len() > 1 {
+                let before = self.anchors[self.anchors.len() - 2];
+                if before.is_paused && before.measure == measure {
+                    self.anchors.pop();
+                    return;
+                }
+            }
+        }
+        self.anchors.push(TimingAnchor {
+            ts,
+            measure,
+            bpm,
+            is_paused,
+        });
+    }
+
+    pub(crate) fn last_bpm(&self) -> Option<f64> {
+        self.anchors.last().and_then(|x| x.bpm)
+    }
+
+    /// The anchor to extrapolate from for times before the first one.
+    fn first_with_bpm(&self) -> Option<&TimingAnchor> {
+        self.anchors.iter().find(|x| x.measure_dur() > 0.0)
+    }
+
+    /// Measures since the start of the chart at `ts`. Negative before the chart starts.
+    pub fn measure_at(&self, ts: f64) -> f64 {
+        let i = self.anchors.partition_point(|x| x.ts <= ts);
+        let anchor = match i.checked_sub(1) {
+            Some(i) => &self.anchors[i],
+            None => match self.first_with_bpm() {
+                Some(x) => x,
+                None => return 0.0,
+            },
+        };
+        let measure_dur = anchor.measure_dur();
+        if measure_dur > 0.0 {
+            anchor.measure.to_f64() + (ts - anchor.ts) / measure_dur
+        } else {
+            anchor.measure.to_f64()
+        }
+    }
+
+    pub fn beat_at(&self, ts: f64) -> f64 {
+        self.measure_at(ts) * 4.0
+    }
+
+    /// The earliest time `measure` is reached.
+    pub fn ts_at_measure(&self, measure: f64) -> f64 {
+        let i = self
+            .anchors
+            .partition_point(|x| x.measure.to_f64() < measure);
+        if let Some(anchor) = self.anchors.get(i) {
+            if anchor.measure.to_f64() == measure {
+                return anchor.ts;
+            }
+        }
+        let anchor = match i.checked_sub(1) {
+            Some(i) => &self.anchors[i],
+            None => match self.first_with_bpm() {
+                Some(x) => x,
+                None => return self.anchors.first().map_or(0.0, |x| x.ts),
+            },
+        };
+        anchor.ts + (measure - anchor.measure.to_f64()) * anchor.measure_dur()
+    }
+
+    pub fn ts_at_beat(&self, beat: f64) -> f64 {
+        self.ts_at_measure(beat / 4.0)
+    }
+
+    /// The BPM in effect at `ts`, `None` before the first BPM change.
+    pub fn bpm_at(&self, ts: f64) -> Option<f64> {
+        let i = self.anchors.partition_point(|x| x.ts <= ts);
+        self.anchors[..i].last().and_then(|x| x.bpm)
+    }
+
+    /// The number and timestamp of every measure starting between `start_ts` and `end_ts`,
+    /// inclusive. Empty if either bound isn't finite or is too far for measures to be counted.
+    pub fn measure_boundaries(&self, start_ts: f64, end_ts: f64) -> Vec<(i64, f64)> {
+        if !(start_ts.is_finite() && end_ts.is_finite()) {
+            return vec![];
+        }
+        // measures can't be told apart past 2^53, and `as i64` saturates, which would make the
+        // range practically endless
+        const MAX_MEASURE: f64 = (1u64 << 53) as f64;
+        let (first, last) = (self.measure_at(start_ts), self.measure_at(end_ts));
+        if !(first.abs() <= MAX_MEASURE && last.abs() <= MAX_MEASURE) {
+            return vec![];
+        }
+        let (first, last) = (first.ceil() as i64, last.floor() as i64);
+        (first..=last)
+            .map(|x| (x, self.ts_at_measure(x as f64)))
+            .filter(|&(_, ts)| start_ts <= ts && ts <= end_ts)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rational() {
+        let third = Rational::new(1, 3);
+        let sum = (0..3).fold(Rational::ZERO, |acc, _| acc + third);
+        assert_eq!(sum, Rational::from(1));
+        assert_eq!(Rational::new(2, -4), Rational::new(-1, 2));
+        assert_eq!(
+            (Rational::new(1, 6) + Rational::new(1, 4)).to_string(),
+            "5/12"
+        );
+        assert_eq!(
+            Rational::new(1, 4) - Rational::new(1, 2),
+            Rational::new(-1, 4)
+        );
+        assert!(Rational::new(1, 3) < Rational::new(34, 100));
+        assert_eq!(
+            Rational::new(3, 10) * Rational::new(5, 6),
+            Rational::new(1, 4)
+        );
+        assert_eq!(Rational::new(7, 3).floor(), 2);
+        assert_eq!(Rational::new(-1, 3).floor(), -1);
+        assert_eq!(Rational::ZERO.to_string(), "0");
+
+        // many small steps still land exactly on the grid
+        let step = Rational::new(1, 192);
+        let mut pos = Rational::ZERO;
+        for _ in 0..192 * 500 {
+            pos += step;
+        }
+        assert_eq!(pos, Rational::from(500));
+
+        // results that don't fit are `None` instead of wrapping around
+        let (a, b) = (Rational::new(1, 999983), Rational::new(1, 999979));
+        let ab = Rational::new(1, 999983 * 999979);
+        assert_eq!(a.checked_mul(b), Some(ab));
+        assert_eq!(ab.checked_add(ab), Some(Rational::new(2, 999983 * 999979)));
+        assert_eq!(ab.checked_mul(ab), None);
+        assert_eq!(ab.checked_add(Rational::new(1, 999961 * 999959)), None);
+        assert_eq!(
+            Rational::from(i64::MAX).checked_add(Rational::from(1)),
+            None
+        );
+        assert_eq!(
+            Rational::from(i64::MIN).checked_sub(Rational::from(i64::MIN)),
+            Some(Rational::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_timing_map() {
+        use crate::container::parse_maidata_insns;
+        use crate::materialize::MaterializationContext;
+
+        // 2 measures at 120, 1 at 60, then 2 notes of 0.5s, and 1 more measure at 60
+        let (insns, _) = parse_maidata_insns("(120){1}1,1,(60)1,{#0.5}1,1,{1}1,1,");
+        let mut mcx = MaterializationContext::with_offset(1.0);
+        mcx.materialize_insns(&insns);
+        let map = mcx.timing_map();
+
+        assert_eq!(map.measure_at(0.0), -0.5);
+        assert_eq!(map.measure_at(1.0), 0.0);
+        assert_eq!(map.beat_at(4.0), 6.0);
+        assert_eq!(map.measure_at(5.0), 2.0);
+        assert_eq!(map.measure_at(9.5), 3.0);
+        assert_eq!(map.measure_at(10.0), 3.0);
+        assert_eq!(map.measure_at(12.0), 3.5);
+
+        assert_eq!(map.ts_at_measure(-0.5), 0.0);
+        assert_eq!(map.ts_at_beat(6.0), 4.0);
+        assert_eq!(map.ts_at_measure(3.0), 9.0);
+        assert_eq!(map.ts_at_measure(3.5), 12.0);
+
+        assert_eq!(map.bpm_at(0.5), None);
+        assert_eq!(map.bpm_at(1.0), Some(120.0));
+        assert_eq!(map.bpm_at(5.0), Some(60.0));
+        assert_eq!(map.bpm_at(9.7), Some(60.0));
+
+        assert_eq!(
+            map.measure_boundaries(0.0, 14.0),
+            vec![(0, 1.0), (1, 3.0), (2, 5.0), (3, 9.0), (4, 14.0)]
+        );
+        // e.g. a seek bar without an end yet
+        assert!(map.measure_boundaries(0.0, f64::INFINITY).is_empty());
+        assert!(map.measure_boundaries(f64::NEG_INFINITY, 14.0).is_empty());
+        assert!(map.measure_boundaries(f64::NAN, 14.0).is_empty());
+        // too many measures to be counted
+        let mut map = TimingMap::default();
+        map.push(0.0, Rational::ZERO, Some(f64::MAX), false);
+        assert!(map.measure_boundaries(0.0, 1.0).is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+//! A uniform view of [`PError`]s and [`PWarning`]s for tools like editors and linters.
+
+use super::{PError, PWarning, Sp, Span, State};
+use serde::Serialize;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A secondary span with an explanation, e.g. where a duplicated key first appears.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A machine-applicable fix: replace the text at `span` with `replacement`.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Fix {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Diagnostic {
+    /// Stable identifier of the kind of problem, e.g. `E015`.
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub fix: Option<Fix>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}[{}]: {}",
+            self.span, self.severity, self.code, self.message
+        )
+    }
+}
+
+impl PError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PError::UnknownChar(_) => "E001",
+            PError::MissingBefore { .. } => "E002",
+            PError::MissingAfter { .. } => "E003",
+            PError::MissingBetween { .. } => "E004",
+            PError::MissingBeatCount => "E005",
+            PError::MissingDuration(_) => "E006",
+            PError::MissingNote => "E007",
+            PError::MissingSlideStartKey => "E008",
+            PError::MissingSlideTrack => "E009",
+            PError::MissingSlideDestinationKey => "E010",
+            PError::InvalidBpm(_) => "E011",
+            PError::InvalidBeatDivisor(_) => "E012",
+            PError::InvalidDuration(_) => "E013",
+            PError::InvalidSlideStopTime(_) => "E014",
+            PError::InvalidSlideTrack { .. } => "E015",
+            PError::InvalidOffset(_) => "E016",
+            PError::InvalidLevel(_) => "E017",
+            PError::InvalidActiveMessageTime(_) => "E018",
+            PError::InvalidNote(_) => "E019",
+            PError::DuplicateShapeModifier(_) => "E020",
+        }
+    }
+}
+
+impl PWarning {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PWarning::DuplicateModifier(..) => "W001",
+            PWarning::MissingSlideStartKey => "W002",
+            PWarning::UnknownKey(_) => "W003",
+            PWarning::DuplicateKey { .. } => "W004",
+            PWarning::ActiveMessageCountMismatch { .. } => "W005",
+            PWarning::MissingChainedSlideDuration(_) => "W006",
+        }
+    }
+}
+
+impl From<&Sp<PError>> for Diagnostic {
+    fn from(error: &Sp<PError>) -> Self {
+        let span = error.span();
+        let fix = match &**error {
+            PError::InvalidSlideTrack {
+                start,
+                track,
+                suggestion: Some(suggestion),
+            } => Some(Fix {
+                message: format!(
+                    "replace `{}{}` with `{}{}`",
+                    start, track, start, suggestion
+                ),
+                span,
+                replacement: suggestion.clone(),
+            }),
+            _ => None,
+        };
+
+        Diagnostic {
+            code: error.code(),
+            severity: Severity::Error,
+            message: (**error).to_string(),
+            span,
+            labels: vec![],
+            fix,
+        }
+    }
+}
+
+impl From<&Sp<PWarning>> for Diagnostic {
+    fn from(warning: &Sp<PWarning>) -> Self {
+        let labels = match &**warning {
+            PWarning::DuplicateKey { first, .. } => vec![Label {
+                span: *first,
+                message: "first defined here".to_string(),
+            }],
+            _ => vec![],
+        };
+        let fix = match &**warning {
+            PWarning::MissingChainedSlideDuration(dur) => Some(Fix {
+                message: format!("add `[{}]`", dur),
+                span: warning.span(),
+                replacement: format!("[{}]", dur),
+            }),
+            _ => None,
+        };
+
+        Diagnostic {
+            code: warning.code(),
+            severity: Severity::Warning,
+            message: (**warning).to_string(),
+            span: warning.span(),
+            labels,
+            fix,
+        }
+    }
+}
+
+impl State {
+    /// All errors and warnings as [`Diagnostic`]s, in source order.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut result = self
+            .errors
+            .iter()
+            .map(Diagnostic::from)
+            .chain(self.warnings.iter().map(Diagnostic::from))
+            .collect::<Vec<_>>();
+        result.sort_by_key(|x| (x.span.byte_offset, x.severity));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{lex_maidata, parse_maidata_insns};
+
+    #[test]
+    fn test_diagnostics() {
+        let (_, state) = parse_maidata_insns("(120){4}1-1[4:1],\n1-5[4:1]*-1 [8:1],");
+        let diagnostics = state.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code, "E015");
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        let fix = diagnostics[0].fix.as_ref().unwrap();
+        assert_eq!(fix.message, "replace `1-1[4:1]` with `1<1[4:1]`");
+        assert_eq!(fix.replacement, "<1[4:1]");
+        assert_eq!((fix.span.col, fix.span.end_col), (10, 17));
+        let fix = diagnostics[1].fix.as_ref().unwrap();
+        assert_eq!(fix.replacement, "<1[8:1]");
+        assert_eq!((fix.span.line, fix.span.col, fix.span.end_col), (2, 10, 18));
+
+        let (_, state) = parse_maidata_insns("1-2[4:1],");
+        assert_eq!(state.diagnostics()[0].fix, None);
+
+        let (_, state) = parse_maidata_insns("1-4[8:1]q7 ,");
+        let diagnostics = state.diagnostics();
+        assert_eq!(diagnostics[0].code, "W006");
+        let fix = diagnostics[0].fix.as_ref().unwrap();
+        assert_eq!(fix.replacement, "[8:1]");
+        assert_eq!((fix.span.col, fix.span.len), (11, 0));
+
+        let (_, state) = lex_maidata("&title=a\n&artist=b\n&title=c\n");
+        let diagnostics = state.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "W004");
+        assert_eq!(diagnostics[0].span.line, 3);
+        assert_eq!(diagnostics[0].labels[0].span.line, 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "[3:2-3:7]warning[W004]: duplicate key `title`"
+        );
+    }
+}
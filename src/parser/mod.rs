@@ -1,13 +1,17 @@
+mod diagnostic;
 mod note;
 mod position;
+mod render;
 mod span;
 mod state;
 mod utils;
 
 use crate::insn::*;
+pub use diagnostic::*;
 use nom::character::complete::multispace0;
 use note::{t_bundle, t_tap_multi_simplified};
 use position::*;
+pub use render::*;
 pub use span::*;
 pub use state::*;
 use utils::*;
@@ -19,7 +23,7 @@ pub(crate) fn parse_maidata_insns(s: NomSpan) -> PResult<Vec<SpRawInsn>> {
     Ok((s, insns.into_iter().flatten().collect()))
 }
 
-fn parse_one_maidata_insn(s: NomSpan) -> PResult<Option<SpRawInsn>> {
+pub(crate) fn parse_one_maidata_insn(s: NomSpan) -> PResult<Option<SpRawInsn>> {
     use nom::branch::alt;
     use nom::combinator::map;
 
@@ -28,7 +32,7 @@ fn parse_one_maidata_insn(s: NomSpan) -> PResult<Option<SpRawInsn>> {
         t_beat_divisor,
         t_rest,
         t_tap_multi_simplified,
-        t_bundle,
+        rollback_on_err(t_bundle),
         t_end_mark,
         map(t_comment, |_| None),
         // TODO: handle unknown characters
@@ -36,7 +40,7 @@ fn parse_one_maidata_insn(s: NomSpan) -> PResult<Option<SpRawInsn>> {
     ))(s)
 }
 
-fn t_comment(s: NomSpan) -> PResult<()> {
+pub(crate) fn t_comment(s: NomSpan) -> PResult<()> {
     use nom::bytes::complete::tag;
 
     let (s, _) = tag("||")(s)?;
@@ -115,15 +119,17 @@ fn t_beat_divisor_param_int(s: NomSpan) -> PResult<Option<BeatDivisorParams>> {
     let (s, divisor_str) = digit1(s)?;
     let (s, end_loc) = nom_locate::position(s)?;
 
-    let divisor: u32 = divisor_str.fragment().parse().unwrap();
-
-    if divisor == 0 {
-        s.extra.borrow_mut().add_error(
-            PError::InvalidBeatDivisor(divisor.to_string()),
-            (start_loc, end_loc).into(),
-        );
-        return Ok((s, None));
-    }
+    let divisor = match divisor_str.fragment().parse::<u32>() {
+        Ok(divisor) if divisor != 0 => divisor,
+        // zero, or too large for u32
+        _ => {
+            s.extra.borrow_mut().add_error(
+                PError::InvalidBeatDivisor(divisor_str.fragment().to_string()),
+                (start_loc, end_loc).into(),
+            );
+            return Ok((s, None));
+        }
+    };
     Ok((s, Some(BeatDivisorParams::NewDivisor(divisor))))
 }
 
@@ -10,23 +10,81 @@ pub fn t_bundle_note(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
     nom::branch::alt((t_hold, t_touch_hold, t_slide, t_tap, t_touch))(s)
 }
 
+/// A note followed by a separator or the end of the line. If it doesn't parse, everything up to
+/// the next `,`, `/`, `` ` ``, newline, `(` or `{` is reported as a single invalid note, so that
+/// the rest of the bundle and the instructions after it are kept.
+pub fn t_bundle_note_recover(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
+    use nom::bytes::complete::take_till1;
+    use nom::character::complete::one_of;
+    use nom::combinator::peek;
+    use nom::Slice;
+
+    let checkpoint = s.extra.borrow().checkpoint();
+    if let Ok((rest, note)) = t_bundle_note(s) {
+        let at_sep = peek(ws(one_of::<_, _, nom::error::Error<_>>(",/`")))(rest).is_ok();
+        // a note that didn't parse is kept with its own message if it has exactly one, like
+        // "invalid slide track"; several are a cascade from misreading the token
+        let keep = match (&note, &s.extra.borrow().errors[checkpoint.1..]) {
+            (Some(_), _) | (None, [_]) => true,
+            (None, _) => false,
+        };
+        // a note ending the line or the input is complete too, the bundle reports the missing `,`
+        let at_line_end = matches!(
+            rest.fragment()
+                .trim_start_matches([' ', '\t'])
+                .chars()
+                .next(),
+            None | Some('\n' | '\r')
+        );
+        if (at_sep && keep) || (at_line_end && note.is_some()) {
+            return Ok((rest, note));
+        }
+    }
+    // the messages of the failed attempt are replaced by a single one
+    s.extra.borrow_mut().rollback(checkpoint);
+
+    let (rest, token) = take_till1(|c| matches!(c, ',' | '/' | '`' | '\n' | '(' | '{'))(s)?;
+    let text = token.fragment().trim_end();
+    let (_, end_loc) = nom_locate::position(token.slice(text.len()..))?;
+    s.extra.borrow_mut().add_error(
+        PError::InvalidNote(text.to_owned()),
+        (token, end_loc).into(),
+    );
+
+    Ok((rest, None))
+}
+
+/// The first note of a bundle only recovers if it looks like a note, so that other instructions
+/// like `E` and stray characters are left to the other parsers.
+fn t_bundle_first_note(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
+    let mut chars = s.fragment().chars();
+    let looks_like_note = match (chars.next(), chars.next()) {
+        (Some('0'..='9' | 'C'), _) => true,
+        (Some('A'..='E'), Some(c)) => c.is_ascii_digit(),
+        _ => false,
+    };
+    if looks_like_note {
+        t_bundle_note_recover(s)
+    } else {
+        t_bundle_note(s)
+    }
+}
+
 pub fn t_bundle_sep_note(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
     use nom::character::complete::char;
 
     let (s, _) = char('/')(s)?;
-    let (s, note) = ws(t_bundle_note).expect(PError::MissingNote)(s)?;
+    let (s, note) = ws(t_bundle_note_recover).expect(PError::MissingNote)(s)?;
 
     Ok((s, note.flatten()))
 }
 
-pub fn t_bundle(s: NomSpan) -> PResult<Option<SpRawInsn>> {
+/// `note/note/...`, returns `None` if all notes are invalid.
+pub fn t_bundle_group(s: NomSpan) -> PResult<Option<VecSp<RawNoteInsn>>> {
     use nom::multi::many0;
 
-    let (s, start_loc) = nom_locate::position(s)?;
-    let (s, first) = t_bundle_note(s)?;
+    let (s, first) = t_bundle_first_note(s)?;
     let (s, rest) = many0(ws(t_bundle_sep_note))(s)?;
-    let (s, _) = ws(t_note_sep)(s)?;
-    let (s, end_loc) = nom_locate::position(s)?;
 
     let notes = {
         let mut tmp = Vec::with_capacity(rest.len() + 1);
@@ -37,8 +95,177 @@ pub fn t_bundle(s: NomSpan) -> PResult<Option<SpRawInsn>> {
     if notes.is_empty() {
         return Ok((s, None));
     }
+    Ok((s, Some(notes)))
+}
+
+pub fn t_bundle_sep_group(s: NomSpan) -> PResult<Option<VecSp<RawNoteInsn>>> {
+    use nom::character::complete::char;
+
+    let (s, _) = char('`')(s)?;
+    let (s, group) = ws(t_bundle_group).expect(PError::MissingNote)(s)?;
+
+    Ok((s, group.flatten()))
+}
+
+pub fn t_bundle(s: NomSpan) -> PResult<Option<SpRawInsn>> {
+    use nom::combinator::opt;
+    use nom::multi::many0;
+
+    let (s, start_loc) = nom_locate::position(s)?;
+    let (s, first) = t_bundle_group(s)?;
+    // pseudo EACH: groups separated by backticks
+    let (s, rest) = many0(ws(t_bundle_sep_group))(s)?;
+    let (s, notes_end_loc) = nom_locate::position(s)?;
+    let (s, sep) = opt(ws(t_note_sep))(s)?;
+    if sep.is_none() {
+        // e.g. a line ending in an invalid note, the next line still parses
+        s.extra.borrow_mut().add_error(
+            PError::MissingAfter {
+                missing: "`,`".to_owned(),
+                previous: "notes".to_owned(),
+            },
+            (notes_end_loc, notes_end_loc).into(),
+        );
+    }
+    let (s, end_loc) = nom_locate::position(s)?;
+
+    let is_pseudo_each = !rest.is_empty();
+    let mut groups = {
+        let mut tmp = Vec::with_capacity(rest.len() + 1);
+        tmp.push(first);
+        tmp.extend(rest);
+        tmp.into_iter().flatten().collect::<Vec<_>>()
+    };
+    if groups.is_empty() {
+        return Ok((s, None));
+    }
 
     let span = (start_loc, end_loc);
-    // TODO: check len before flattening?
-    Ok((s, Some(RawInsn::Notes(notes).with_span(span))))
+    if is_pseudo_each {
+        Ok((s, Some(RawInsn::PseudoEach(groups).with_span(span))))
+    } else {
+        Ok((s, Some(RawInsn::Notes(groups.remove(0)).with_span(span))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{test_parser_err, test_parser_ok};
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_t_bundle() -> Result<(), Box<dyn Error>> {
+        let tap = |key: u8| -> RawNoteInsn {
+            RawNoteInsn::Tap(TapParams {
+                key: key.try_into().unwrap(),
+                modifier: Default::default(),
+            })
+        };
+
+        assert_eq!(
+            *test_parser_ok(t_bundle, "1/2,", "").unwrap(),
+            RawInsn::Notes(vec![
+                tap(0).with_span(Span::empty_at(0, 1, 1)),
+                tap(1).with_span(Span::empty_at(0, 1, 1))
+            ])
+        );
+        assert_eq!(
+            *test_parser_ok(t_bundle, "1`2 ` 3/4,", "").unwrap(),
+            RawInsn::PseudoEach(vec![
+                vec![tap(0).with_span(Span::empty_at(0, 1, 1))],
+                vec![tap(1).with_span(Span::empty_at(0, 1, 1))],
+                vec![
+                    tap(2).with_span(Span::empty_at(0, 1, 1)),
+                    tap(3).with_span(Span::empty_at(0, 1, 1))
+                ],
+            ])
+        );
+
+        test_parser_err(t_bundle, "1``2,");
+        test_parser_err(t_bundle, "1`,");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_t_bundle_recover() {
+        let parse = |x: &str| {
+            let state = std::cell::RefCell::new(State::default());
+            let (rest, insn) = t_bundle(NomSpan::new_extra(x, &state)).unwrap();
+            assert_eq!(*rest.fragment(), "");
+            (insn.map(|x| (*x).to_string()), state.into_inner().errors)
+        };
+
+        let (insn, errors) = parse("1-9[4:1]/2 ,");
+        assert_eq!(insn.as_deref(), Some("2,"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(*errors[0], PError::InvalidNote("1-9[4:1]".to_owned()));
+        assert_eq!((errors[0].span().col, errors[0].span().end_col), (1, 9));
+
+        let (insn, errors) = parse("1/2k /3xq`4,");
+        assert_eq!(insn.as_deref(), Some("1`4,"));
+        assert_eq!(
+            errors.iter().map(|x| (**x).clone()).collect::<Vec<_>>(),
+            vec![
+                PError::InvalidNote("2k".to_owned()),
+                PError::InvalidNote("3xq".to_owned())
+            ]
+        );
+
+        // a single specific error is kept
+        let (insn, errors) = parse("1-2[4:1]/3,");
+        assert_eq!(insn.as_deref(), Some("3,"));
+        assert!(matches!(*errors[0], PError::InvalidSlideTrack { .. }));
+
+        let (insn, errors) = parse("12@3,");
+        assert_eq!(insn, None);
+        assert_eq!(errors.len(), 1);
+
+        // the invalid note ends at the line, a BPM change on the next one still applies
+        let (insns, state) = crate::container::parse_maidata_insns("1/2k\n(120)3,");
+        assert_eq!(
+            insns.iter().map(|x| (**x).to_string()).collect::<Vec<_>>(),
+            vec!["1,", "(120)", "3,"]
+        );
+        assert_eq!(
+            state
+                .errors
+                .iter()
+                .map(|x| (**x).clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PError::InvalidNote("2k".to_owned()),
+                PError::MissingAfter {
+                    missing: "`,`".to_owned(),
+                    previous: "notes".to_owned(),
+                }
+            ]
+        );
+        let span = state.errors[1].span();
+        assert_eq!((span.line, span.col, span.end_col), (1, 5, 5));
+
+        // a valid note missing only its `,` isn't an invalid note
+        for source in ["1,2", "1,2 \n(120)3,"] {
+            let (insns, state) = crate::container::parse_maidata_insns(source);
+            assert_eq!((*insns[1]).to_string(), "2,", "{}", source);
+            assert_eq!(
+                state
+                    .errors
+                    .iter()
+                    .map(|x| (**x).clone())
+                    .collect::<Vec<_>>(),
+                vec![PError::MissingAfter {
+                    missing: "`,`".to_owned(),
+                    previous: "notes".to_owned(),
+                }],
+                "{}",
+                source
+            );
+        }
+
+        let (insns, state) = crate::container::parse_maidata_insns("1/2k{8}3,");
+        assert_eq!(insns.len(), 3);
+        assert_eq!(*state.errors[0], PError::InvalidNote("2k".to_owned()));
+    }
 }
@@ -1,25 +1,41 @@
 use super::*;
+use crate::materialize::Rational;
 
 pub fn t_dur_spec_num_beats_params(s: NomSpan) -> PResult<Option<NumBeatsParams>> {
     use nom::character::complete::{char, digit1};
+    use nom::combinator::{opt, recognize};
+    use nom::sequence::pair;
 
-    // TODO: support floating point
     let (s, start_loc) = nom_locate::position(s)?;
     let (s, divisor_str) = digit1(s)?;
     let (s, _) = ws(char(':'))(s)?;
-    let (s, num_str) = ws(digit1).expect(PError::MissingBeatsNum)(s)?;
+    // the number of beats can be fractional, like `1.5`, but without sign or exponent
+    let num = recognize(pair(digit1, opt(pair(char('.'), digit1))));
+    let (s, num_str) = ws(num).expect(PError::MissingBeatCount)(s)?;
     let (s, end_loc) = nom_locate::position(s)?;
     if num_str.is_none() {
         return Ok((s, None));
     }
 
-    // TODO: handle conversion errors
-    let divisor = divisor_str.fragment().parse().unwrap();
-    let num = num_str.unwrap().fragment().parse().unwrap();
+    let num_str = num_str.unwrap();
+    let (divisor, num) = match (
+        divisor_str.fragment().parse::<u32>(),
+        parse_decimal(num_str.fragment()),
+    ) {
+        (Ok(divisor), Some(num)) => (divisor, num),
+        // e.g. a divisor too large for u32
+        _ => {
+            s.extra.borrow_mut().add_error(
+                PError::InvalidDuration(format!("{}:{}", divisor_str, num_str)),
+                (start_loc, end_loc).into(),
+            );
+            return Ok((s, None));
+        }
+    };
 
     if divisor == 0 {
         s.extra.borrow_mut().add_error(
-            PError::InvalidBeatDivisor(format!("{}:{}", divisor, num)),
+            PError::InvalidBeatDivisor(format!("{}:{}", divisor, num_str)),
             (start_loc, end_loc).into(),
         );
         return Ok((s, None));
@@ -34,6 +50,14 @@ pub fn t_dur_spec_num_beats_params(s: NomSpan) -> PResult<Option<NumBeatsParams>
     ))
 }
 
+/// `1.5` as the exact fraction 3/2, `None` if it doesn't fit.
+fn parse_decimal(x: &str) -> Option<Rational> {
+    let (int, frac) = x.split_once('.').unwrap_or((x, ""));
+    let den = 10i64.checked_pow(frac.len() as u32)?;
+    let num = format!("{}{}", int, frac).parse::<i64>().ok()?;
+    Some(Rational::new(num, den))
+}
+
 pub fn t_dur_spec_bpm_num_beats_params(s: NomSpan) -> PResult<Option<NumBeatsParams>> {
     use nom::character::complete::char;
     use nom::number::complete::double;
@@ -105,7 +129,7 @@ mod tests {
             Duration::NumBeats(NumBeatsParams {
                 bpm: None,
                 divisor: 4,
-                num: 3
+                num: Rational::from(3)
             })
         );
         assert_eq!(
@@ -113,14 +137,26 @@ mod tests {
             Duration::NumBeats(NumBeatsParams {
                 bpm: None,
                 divisor: 1,
-                num: 0
+                num: Rational::from(0)
             })
         );
         test_parser_err(t_dur, "[1:]");
         test_parser_err(t_dur, "[0:1]");
         test_parser_err(t_dur, " [4:3]");
         test_parser_err(t_dur, "[4.5:2]");
-        test_parser_err(t_dur, "[4:2.5]");
+        assert_eq!(
+            test_parser_ok(t_dur, "[8:1.5]", "").unwrap(),
+            Duration::NumBeats(NumBeatsParams {
+                bpm: None,
+                divisor: 8,
+                num: Rational::new(3, 2)
+            })
+        );
+        test_parser_err(t_dur, "[4:2.]");
+        test_parser_err(t_dur, "[4:.5]");
+        test_parser_err(t_dur, "[4:1e2]");
+        test_parser_err(t_dur, "[99999999999:1]");
+        test_parser_err(t_dur, &format!("[4:1{}]", "0".repeat(400)));
 
         assert_eq!(
             test_parser_ok(t_dur, "[#2.5]", " ,").unwrap(),
@@ -145,12 +181,19 @@ mod tests {
             Duration::NumBeats(NumBeatsParams {
                 bpm: Some(120.0),
                 divisor: 4,
-                num: 1
+                num: Rational::from(1)
             })
         );
         test_parser_err(t_dur, "[0#1:1]");
         test_parser_err(t_dur, "[120#0:1]");
-        test_parser_err(t_dur, "[120#4:1.5]");
+        assert_eq!(
+            test_parser_ok(t_dur, "[120#4:1.5]", "").unwrap(),
+            Duration::NumBeats(NumBeatsParams {
+                bpm: Some(120.0),
+                divisor: 4,
+                num: Rational::new(3, 2)
+            })
+        );
 
         test_parser_err(t_dur, "[4:1#160]");
         test_parser_err(t_dur, "[4:1#4:1]");
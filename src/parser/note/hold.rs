@@ -12,7 +12,7 @@ pub fn t_hold_modifier_str(s: NomSpan) -> PResult<Vec<char>> {
 
 pub fn t_hold(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
     use nom::character::complete::char;
-    use nom::combinator::map;
+    use nom::combinator::{map, opt};
 
     let (s, start_loc) = nom_locate::position(s)?;
     let (s, key) = t_key(s)?;
@@ -23,7 +23,8 @@ pub fn t_hold(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
         m.extend(modifier_str.clone());
         m
     })(s)?;
-    let (s, dur) = ws(t_dur).expect(PError::MissingDuration(NoteType::Hold))(s)?;
+    // the duration may be omitted entirely, e.g. `1h,`
+    let (s, dur) = opt(ws(t_dur))(s)?;
     let (s, end_loc) = nom_locate::position(s)?;
 
     let mut modifier = HoldModifier::default();
@@ -51,10 +52,66 @@ pub fn t_hold(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
         }
     }
 
+    // errors are already reported if the duration is present but invalid
+    let dur = match dur {
+        Some(dur) => dur.map(Some),
+        None => Some(None),
+    };
+
     let span = (start_loc, end_loc);
     Ok((
         s,
-        dur.flatten()
-            .map(|dur| RawNoteInsn::Hold(HoldParams { key, dur, modifier }).with_span(span)),
+        dur.map(|dur| RawNoteInsn::Hold(HoldParams { key, dur, modifier }).with_span(span)),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{test_parser_err, test_parser_ok};
+    use super::*;
+    use crate::materialize::Rational;
+    use std::error::Error;
+
+    #[test]
+    fn test_t_hold() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            *test_parser_ok(t_hold, "1bh[4:1]", ",").unwrap(),
+            RawNoteInsn::Hold(HoldParams {
+                key: 0.try_into().unwrap(),
+                dur: Some(Duration::NumBeats(NumBeatsParams {
+                    bpm: None,
+                    divisor: 4,
+                    num: Rational::from(1)
+                })),
+                modifier: HoldModifier {
+                    is_break: true,
+                    is_ex: false,
+                },
+            })
+        );
+        assert_eq!(
+            *test_parser_ok(t_hold, "2h", ",").unwrap(),
+            RawNoteInsn::Hold(HoldParams {
+                key: 1.try_into().unwrap(),
+                dur: None,
+                modifier: Default::default(),
+            })
+        );
+        assert_eq!(
+            *test_parser_ok(t_hold, "3 x h", "/4").unwrap(),
+            RawNoteInsn::Hold(HoldParams {
+                key: 2.try_into().unwrap(),
+                dur: None,
+                modifier: HoldModifier {
+                    is_break: false,
+                    is_ex: true,
+                },
+            })
+        );
+
+        test_parser_err(t_hold, "1h[4:1");
+        test_parser_err(t_hold, "1h[0:1]");
+
+        Ok(())
+    }
+}
@@ -213,18 +213,28 @@ pub fn t_slide_track_modifier(
 }
 
 // TODO: refactor
-pub fn t_slide_segment_group(
-    s: NomSpan,
-) -> PResult<(Vec<SlideSegment>, Option<SlideDuration>, SlideTrackModifier)> {
-    // TODO: track with different speed
+/// Segments, duration and modifier of a slide segment group. The duration is `Err` with where
+/// it should be if there is none, which is left to the track to report as only the last group of
+/// a chained slide may omit it.
+type SegmentGroup = (
+    Vec<SlideSegment>,
+    Result<Option<SlideDuration>, Span>,
+    SlideTrackModifier,
+);
+
+pub fn t_slide_segment_group(s: NomSpan) -> PResult<SegmentGroup> {
+    use nom::combinator::opt;
+
     let (s, segments) = ws_list1(t_slide_segment)(s)?;
     let segments = segments.into_iter().flatten().collect::<Vec<_>>();
     // TODO: warn if have modifier before dur
     let (s, modifier) = t_slide_track_modifier(s, SlideTrackModifier::default())?;
-    let (s, dur) = ws(t_slide_dur).expect(PError::MissingDuration(NoteType::Slide))(s)?;
+    let (s, dur_loc) = nom_locate::position(s)?;
+    let (s, dur) = opt(ws(t_slide_dur))(s)?;
     let (s, modifier) = t_slide_track_modifier(s, modifier)?;
 
-    Ok((s, (segments, dur.flatten(), modifier)))
+    let dur = dur.ok_or_else(|| (dur_loc, dur_loc).into());
+    Ok((s, (segments, dur, modifier)))
 }
 
 pub fn validate_slide_track(start_key: Key, track: &SlideTrack) -> bool {
@@ -234,8 +244,30 @@ pub fn validate_slide_track(start_key: Key, track: &SlideTrack) -> bool {
     normalize_slide_track(start_key, track).is_some()
 }
 
+/// Guess the intended track of an invalid one. Currently this turns lines to the key itself into
+/// full circles, e.g. `1-1` into `1<1`.
+fn suggest_slide_track(start_key: Key, track: &SlideTrack) -> Option<SlideTrack> {
+    let mut result = track.clone();
+    let mut key = start_key;
+    let mut changed = false;
+    for segment in result.segments_mut() {
+        if let SlideSegment::Line(params) = *segment {
+            if params.destination == key {
+                *segment = SlideSegment::CircumferenceLeft(params);
+                changed = true;
+            }
+        }
+        key = segment.params().destination;
+    }
+
+    if changed && validate_slide_track(start_key, &result) {
+        Some(result)
+    } else {
+        None
+    }
+}
+
 pub fn t_slide_track(s: NomSpan, start_key: Option<Key>) -> PResult<Option<SlideTrack>> {
-    // TODO: track with different speed
     let (s, start_loc) = nom_locate::position(s)?;
     let (s, groups) = ws_list1(t_slide_segment_group)(s)?;
     let (s, end_loc) = nom_locate::position(s)?;
@@ -252,52 +284,53 @@ pub fn t_slide_track(s: NomSpan, start_key: Option<Key>) -> PResult<Option<Slide
             acc.is_break |= x.is_break;
             acc
         });
-    if groups.len() > 1 {
-        // TODO: message
-        s.extra.borrow_mut().add_warning(
-            PWarning::MultipleSlideTrackGroups,
-            (start_loc, end_loc).into(),
-        );
-    }
-    let dur = groups.iter().fold(None, |acc, (_, dur, _)| {
-        if let Some(acc) = acc {
-            if let Some(dur) = dur {
-                let result: Option<SlideDuration> = acc + *dur;
-                if result.is_none() {
-                    s.extra.borrow_mut().add_error(
-                        PError::DurationMismatch(NoteType::Slide),
-                        (start_loc, end_loc).into(),
-                    );
-                    // TODO
-                    return Some(*dur);
-                }
-                result
-            } else {
-                Some(acc)
+    // every group of a chained slide has its own duration, except that a group missing one may
+    // take the one before it, e.g. `1-4[8:1]q7`; as `ws_list1` is greedy over segments, such a
+    // group only ends before the track does when a modifier splits it, e.g. `1-4[8:1]q7b-3[8:1]`
+    let mut prev_dur: Option<SlideDuration> = None;
+    let mut segment_groups = Vec::with_capacity(groups.len());
+    // every group is looked at for its diagnostics before giving up
+    let mut failed = false;
+    for (segments, dur, _) in groups {
+        let dur = match (dur, prev_dur) {
+            (Ok(dur), _) => dur,
+            (Err(span), Some(prev)) => {
+                // the stop time only applies to the first group
+                let dur = SlideDuration::Simple(prev.slide_duration());
+                s.extra
+                    .borrow_mut()
+                    .add_warning(PWarning::MissingChainedSlideDuration(dur.to_string()), span);
+                Some(dur)
+            }
+            (Err(span), None) => {
+                s.extra
+                    .borrow_mut()
+                    .add_error(PError::MissingDuration(NoteType::Slide), span);
+                None
             }
-        } else {
-            *dur
+        };
+        prev_dur = dur;
+        match (segments.is_empty(), dur) {
+            (false, Some(dur)) => segment_groups.push(SlideSegmentGroup { segments, dur }),
+            // no extra error
+            _ => failed = true,
         }
-    });
-    let segments = groups
-        .into_iter()
-        .flat_map(|(segments, _, _)| segments)
-        .collect::<Vec<_>>();
-    if segments.is_empty() || dur.is_none() {
-        // no extra error
+    }
+    if failed {
         return Ok((s, None));
     }
+    let groups = segment_groups;
 
-    let track = SlideTrack {
-        segments,
-        dur: dur.unwrap(),
-        modifier,
-    };
+    let track = SlideTrack { groups, modifier };
 
     if let Some(start_key) = start_key {
         if !validate_slide_track(start_key, &track) {
             s.extra.borrow_mut().add_error(
-                PError::InvalidSlideTrack(format!("{}{}", start_key, track)),
+                PError::InvalidSlideTrack {
+                    start: start_key.to_string(),
+                    track: track.to_string(),
+                    suggestion: suggest_slide_track(start_key, &track).map(|x| x.to_string()),
+                },
                 (start_loc, end_loc).into(),
                 // "invalid slide track instruction".to_string(),
             );
@@ -380,8 +413,8 @@ pub fn t_slide(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
         }
         let shape = match *x.fragment() {
             "@" => Some(TapShape::Ring),
-            "?" => Some(TapShape::Invalid),
-            "!" => Some(TapShape::Invalid),
+            "?" => Some(TapShape::NoStar),
+            "!" => Some(TapShape::Sudden),
             _ => None,
         };
         if let Some(shape) = shape {
@@ -429,6 +462,7 @@ pub fn t_slide(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
 mod tests {
     use super::super::tests::{test_parser_err, test_parser_ok};
     use super::*;
+    use crate::materialize::Rational;
 
     #[test]
     fn test_t_slide_dur() -> Result<(), Box<dyn std::error::Error>> {
@@ -437,7 +471,7 @@ mod tests {
             SlideDuration::Simple(Duration::NumBeats(NumBeatsParams {
                 bpm: None,
                 divisor: 4,
-                num: 3
+                num: Rational::from(3)
             }))
         );
 
@@ -451,7 +485,7 @@ mod tests {
             SlideDuration::Simple(Duration::NumBeats(NumBeatsParams {
                 bpm: Some(120.0),
                 divisor: 4,
-                num: 1
+                num: Rational::from(1)
             }))
         );
 
@@ -483,7 +517,7 @@ mod tests {
                 Duration::NumBeats(NumBeatsParams {
                     bpm: None,
                     divisor: 4,
-                    num: 1
+                    num: Rational::from(1)
                 })
             )
         );
@@ -494,7 +528,7 @@ mod tests {
                 Duration::NumBeats(NumBeatsParams {
                     bpm: None,
                     divisor: 4,
-                    num: 1
+                    num: Rational::from(1)
                 })
             )
         );
@@ -509,7 +543,7 @@ mod tests {
                 Duration::NumBeats(NumBeatsParams {
                     bpm: Some(160.0),
                     divisor: 4,
-                    num: 1
+                    num: Rational::from(1)
                 })
             )
         );
@@ -520,7 +554,7 @@ mod tests {
                 Duration::NumBeats(NumBeatsParams {
                     bpm: Some(160.0),
                     divisor: 4,
-                    num: 1
+                    num: Rational::from(1)
                 })
             )
         );
@@ -538,4 +572,82 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_t_slide_track_groups() {
+        let group = |x: &str, divisor: u32, num: i64| {
+            let (_, segment) = t_slide_segment(NomSpan::new_extra(x, &Default::default())).unwrap();
+            SlideSegmentGroup {
+                segments: vec![segment.unwrap()],
+                dur: SlideDuration::Simple(Duration::NumBeats(NumBeatsParams {
+                    bpm: None,
+                    divisor,
+                    num: Rational::from(num),
+                })),
+            }
+        };
+
+        let key = Some(Key::new(0).unwrap());
+        let track = test_parser_ok(|s| t_slide_track(s, key), "-4[8:1]q7[8:3]b", ",").unwrap();
+        assert_eq!(track.groups, vec![group("-4", 8, 1), group("q7", 8, 3)]);
+        assert!(track.modifier.is_break);
+        assert_eq!(track.to_string(), "-4[8:1]q7[8:3]b");
+        assert_eq!(track.dur().unwrap().to_string(), "2:1");
+
+        // fractional counts are reduced only when the sum is whole
+        for (text, dur) in [
+            ("-4[8:1.5]q7[8:0.5]", "4:1"),
+            ("-4[8:1.5]q7[4:1]", "8:3.5"),
+            ("-4[3:0.5]q7[6:1]", "3:1"),
+            ("-4[8:0.1]q7[8:0.2]", "8:0.3"),
+        ] {
+            let track = test_parser_ok(|s| t_slide_track(s, key), text, ",").unwrap();
+            assert_eq!(track.to_string(), text);
+            assert_eq!(track.dur().unwrap().to_string(), dur, "{}", text);
+        }
+
+        // a trailing group without a duration takes the one before it, without the stop time
+        let state = std::cell::RefCell::new(State::default());
+        let (rest, track) =
+            t_slide_track(NomSpan::new_extra("-4[1.5##8:1]q7,", &state), key).unwrap();
+        assert_eq!(*rest.fragment(), ",");
+        assert_eq!(track.unwrap().to_string(), "-4[1.5##8:1]q7[8:1]");
+        let state = state.into_inner();
+        assert!(state.errors.is_empty());
+        assert_eq!(
+            *state.warnings[0],
+            PWarning::MissingChainedSlideDuration("8:1".to_owned())
+        );
+        test_parser_err(|s| t_slide_track(s, key), "-4q7,");
+
+        // a modifier splits the greedy segment list, so a group in the middle may take it too
+        let state = std::cell::RefCell::new(State::default());
+        let (_, track) =
+            t_slide_track(NomSpan::new_extra("-4[8:1]q7b-3[8:1],", &state), key).unwrap();
+        assert_eq!(track.unwrap().groups.len(), 3);
+        assert_eq!(state.into_inner().warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_t_slide_head_shape() {
+        for (text, shape, is_sudden) in [
+            ("1-5[4:1]", None, false),
+            ("1@-5[4:1]", Some(TapShape::Ring), false),
+            ("1b?-5[4:1]", Some(TapShape::NoStar), false),
+            ("1!-5[4:1]*-3[4:1]", Some(TapShape::Sudden), true),
+        ] {
+            let note = test_parser_ok(t_slide, text, ",").unwrap();
+            let RawNoteInsn::Slide(params) = &*note else {
+                unreachable!()
+            };
+            assert_eq!(params.start.modifier.shape, shape);
+            assert!(params
+                .tracks
+                .iter()
+                .all(|x| x.modifier.is_sudden == is_sudden));
+            assert_eq!(params.to_string(), text);
+        }
+
+        test_parser_err(t_slide, "1@?-5[4:1]");
+    }
 }
@@ -4,7 +4,7 @@ pub fn t_touch_modifier_str(s: NomSpan) -> PResult<Vec<char>> {
     use nom::character::complete::one_of;
     use nom::multi::many0;
 
-    let (s1, variants) = many0(ws(one_of("f")))(s)?;
+    let (s1, variants) = many0(ws(one_of("bxf")))(s)?;
 
     Ok((if variants.is_empty() { s } else { s1 }, variants))
 }
@@ -17,18 +17,19 @@ pub fn t_touch_param(s: NomSpan) -> PResult<TouchParams> {
 
     let mut modifier = TouchModifier::default();
     for x in &modifier_str {
-        match *x {
-            'f' => {
-                if modifier.is_firework {
-                    s.extra.borrow_mut().add_warning(
-                        PWarning::DuplicateModifier('f', NoteType::Touch),
-                        (start_loc, end_loc).into(),
-                    );
-                }
-                modifier.is_firework = true;
-            }
+        let flag = match *x {
+            'b' => &mut modifier.is_break,
+            'x' => &mut modifier.is_ex,
+            'f' => &mut modifier.is_firework,
             _ => unreachable!(),
+        };
+        if *flag {
+            s.extra.borrow_mut().add_warning(
+                PWarning::DuplicateModifier(*x, NoteType::Touch),
+                (start_loc, end_loc).into(),
+            );
         }
+        *flag = true;
     }
 
     Ok((s, TouchParams { sensor, modifier }))
@@ -63,9 +64,25 @@ mod tests {
             test_parser_ok(t_touch_param, "C 1 f", ""),
             TouchParams {
                 sensor: ('C', None).try_into().unwrap(),
-                modifier: TouchModifier { is_firework: true },
+                modifier: TouchModifier {
+                    is_firework: true,
+                    ..Default::default()
+                },
+            }
+        );
+
+        assert_eq!(
+            test_parser_ok(t_touch_param, "E3bx", ","),
+            TouchParams {
+                sensor: ('E', Some(2)).try_into().unwrap(),
+                modifier: TouchModifier {
+                    is_break: true,
+                    is_ex: true,
+                    is_firework: false,
+                },
             }
         );
+        assert_eq!(test_parser_ok(t_touch_param, "Cfb", ",").to_string(), "Cbf");
 
         test_parser_err(t_touch_param, "");
         test_parser_err(t_touch_param, " A1");
@@ -5,14 +5,14 @@ pub fn t_touch_hold_modifier_str(s: NomSpan) -> PResult<Vec<char>> {
     use nom::character::complete::one_of;
     use nom::multi::many0;
 
-    let (s1, variants) = many0(ws(one_of("f")))(s)?;
+    let (s1, variants) = many0(ws(one_of("bxf")))(s)?;
 
     Ok((if variants.is_empty() { s } else { s1 }, variants))
 }
 
 pub fn t_touch_hold(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
     use nom::character::complete::char;
-    use nom::combinator::map;
+    use nom::combinator::{map, opt};
 
     let (s, start_loc) = nom_locate::position(s)?;
     let (s, sensor) = t_touch_sensor(s)?;
@@ -23,29 +23,37 @@ pub fn t_touch_hold(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
         m.extend(modifier_str.clone());
         m
     })(s)?;
-    let (s, dur) = ws(t_dur).expect(PError::MissingDuration(NoteType::TouchHold))(s)?;
+    // the duration may be omitted entirely, e.g. `Ch,`
+    let (s, dur) = opt(ws(t_dur))(s)?;
     let (s, end_loc) = nom_locate::position(s)?;
 
     let mut modifier = TouchHoldModifier::default();
     for x in &modifier_str {
-        match *x {
-            'f' => {
-                if modifier.is_firework {
-                    s.extra.borrow_mut().add_warning(
-                        PWarning::DuplicateModifier('f', NoteType::TouchHold),
-                        (start_loc, end_loc).into(),
-                    );
-                }
-                modifier.is_firework = true;
-            }
+        let flag = match *x {
+            'b' => &mut modifier.is_break,
+            'x' => &mut modifier.is_ex,
+            'f' => &mut modifier.is_firework,
             _ => unreachable!(),
+        };
+        if *flag {
+            s.extra.borrow_mut().add_warning(
+                PWarning::DuplicateModifier(*x, NoteType::TouchHold),
+                (start_loc, end_loc).into(),
+            );
         }
+        *flag = true;
     }
 
+    // errors are already reported if the duration is present but invalid
+    let dur = match dur {
+        Some(dur) => dur.map(Some),
+        None => Some(None),
+    };
+
     let span = (start_loc, end_loc);
     Ok((
         s,
-        dur.flatten().map(|dur| {
+        dur.map(|dur| {
             RawNoteInsn::TouchHold(TouchHoldParams {
                 sensor,
                 dur,
@@ -55,3 +63,44 @@ pub fn t_touch_hold(s: NomSpan) -> PResult<Option<SpRawNoteInsn>> {
         }),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{test_parser_err, test_parser_ok};
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_t_touch_hold() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            *test_parser_ok(t_touch_hold, "Chf[#1.5]", ",").unwrap(),
+            RawNoteInsn::TouchHold(TouchHoldParams {
+                sensor: ('C', None).try_into().unwrap(),
+                dur: Some(Duration::Seconds(1.5)),
+                modifier: TouchHoldModifier {
+                    is_firework: true,
+                    ..Default::default()
+                },
+            })
+        );
+        assert_eq!(
+            *test_parser_ok(t_touch_hold, "Ch", ",").unwrap(),
+            RawNoteInsn::TouchHold(TouchHoldParams {
+                sensor: ('C', None).try_into().unwrap(),
+                dur: None,
+                modifier: Default::default(),
+            })
+        );
+
+        let note = test_parser_ok(t_touch_hold, "Cbhx[4:1]", ",").unwrap();
+        let RawNoteInsn::TouchHold(params) = &*note else {
+            unreachable!()
+        };
+        assert!(params.modifier.is_break && params.modifier.is_ex);
+        assert_eq!(params.to_string(), "Cbxh[4:1]");
+
+        test_parser_err(t_touch_hold, "Ch[4:");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Rendering [`Diagnostic`]s for terminals, quoting the offending source lines.
+
+use super::{Diagnostic, Severity, Span, State};
+use std::fmt::Write;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+
+/// Renders diagnostics in the style of rustc:
+///
+/// ```text
+/// error[E015]: invalid slide track `1-1[4:1]`
+///  --> 1:10
+///   |
+/// 1 | (120){4}1-1[4:1],
+///   |          ^^^^^^^
+///   = help: replace `1-1[4:1]` with `1<1[4:1]`
+/// ```
+///
+/// `source` must be the text the spans refer to, i.e. the whole file for [`lex_maidata`] and the
+/// note string for [`parse_maidata_insns`].
+///
+/// [`lex_maidata`]: crate::container::lex_maidata
+/// [`parse_maidata_insns`]: crate::container::parse_maidata_insns
+#[derive(Copy, Clone, Debug)]
+pub struct Renderer<'a> {
+    source: &'a str,
+    color: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            color: false,
+        }
+    }
+
+    /// Use ANSI colours, e.g. when `std::io::stdout().is_terminal()`. Defaults to `false`.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Render all messages of `state` in source order, separated by blank lines.
+    pub fn render_state(&self, state: &State) -> String {
+        state
+            .diagnostics()
+            .iter()
+            .map(|x| self.render(x))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let color = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut spans = vec![(diagnostic.span, '^', color, None)];
+        for label in &diagnostic.labels {
+            spans.push((label.span, '-', BLUE, Some(label.message.as_str())));
+        }
+        let gutter_width = spans
+            .iter()
+            .map(|(span, ..)| span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(gutter_width);
+
+        let mut result = String::new();
+        writeln!(
+            result,
+            "{}{}[{}]{}{}: {}{}",
+            self.paint(color),
+            diagnostic.severity,
+            diagnostic.code,
+            self.paint(RESET),
+            self.paint(BOLD),
+            diagnostic.message,
+            self.paint(RESET),
+        )
+        .unwrap();
+        writeln!(
+            result,
+            "{}{}-->{} {}:{}",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            diagnostic.span.line,
+            diagnostic.span.col
+        )
+        .unwrap();
+        writeln!(
+            result,
+            "{} {}|{}",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET)
+        )
+        .unwrap();
+        for (span, marker, color, message) in spans {
+            let (line, padding, width) = self.locate(span);
+            writeln!(
+                result,
+                "{}{:>w$} |{} {}",
+                self.paint(BLUE),
+                span.line,
+                self.paint(RESET),
+                line,
+                w = gutter_width
+            )
+            .unwrap();
+            write!(
+                result,
+                "{} {}|{} {}{}{}",
+                gutter,
+                self.paint(BLUE),
+                self.paint(RESET),
+                padding,
+                self.paint(color),
+                marker.to_string().repeat(width),
+            )
+            .unwrap();
+            if let Some(message) = message {
+                write!(result, " {}", message).unwrap();
+            }
+            writeln!(result, "{}", self.paint(RESET)).unwrap();
+        }
+        if let Some(fix) = &diagnostic.fix {
+            writeln!(
+                result,
+                "{} {}={} {}help{}: {}",
+                gutter,
+                self.paint(BLUE),
+                self.paint(RESET),
+                self.paint(CYAN),
+                self.paint(RESET),
+                fix.message
+            )
+            .unwrap();
+        }
+        result
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.color {
+            code
+        } else {
+            ""
+        }
+    }
+
+    /// The source line containing the start of `span`, the whitespace to put before the
+    /// underline, and the width of the underline. Spans over multiple lines are cut at the end
+    /// of the first one.
+    fn locate(&self, span: Span) -> (&'a str, String, usize) {
+        let start = span.byte_offset.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |x| x + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |x| start + x);
+        let line = self.source[line_start..line_end].trim_end_matches('\r');
+        let end = (start + span.len).min(line_start + line.len()).max(start);
+
+        // keep tabs so that the underline lines up
+        let padding = self.source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.source[start..end].chars().count().max(1);
+        (line, padding, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{lex_maidata, parse_maidata_insns};
+
+    #[test]
+    fn test_render() {
+        let source = "(120){4}1-1[4:1],\n\t1-5[4:1,\n";
+        let (_, state) = parse_maidata_insns(source);
+        assert_eq!(
+            Renderer::new(source).render_state(&state),
+            "error[E015]: invalid slide track `1-1[4:1]`\n \
+             --> 1:10\n  \
+             |\n\
+             1 | (120){4}1-1[4:1],\n  \
+             |          ^^^^^^^\n  \
+             = help: replace `1-1[4:1]` with `1<1[4:1]`\n\
+             \n\
+             error[E003]: expected `]` after slide duration\n \
+             --> 2:9\n  \
+             |\n\
+             2 | \t1-5[4:1,\n  \
+             | \t       ^\n"
+        );
+
+        let source = "&title=a\n&artist=b\n&title=c\n";
+        let (_, state) = lex_maidata(source);
+        let rendered = Renderer::new(source).render_state(&state);
+        assert!(rendered
+            .ends_with("3 | &title=c\n  |  ^^^^^\n1 | &title=a\n  |  ----- first defined here\n"));
+
+        let colored = Renderer::new(source).color(true).render_state(&state);
+        assert!(colored.starts_with("\x1b[1;33mwarning[W004]"));
+    }
+}
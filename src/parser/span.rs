@@ -37,6 +37,47 @@ impl Span {
             len,
         }
     }
+
+    /// An empty span at the given position, for things without source text.
+    pub fn empty_at(byte_offset: usize, line: usize, col: usize) -> Self {
+        Self {
+            byte_offset,
+            line,
+            col,
+            end_line: line,
+            end_col: col,
+            len: 0,
+        }
+    }
+
+    /// The span of the same text in a bigger one, where the text this span is relative to starts
+    /// at `base`.
+    pub(crate) fn offset_by(self, base: Span) -> Self {
+        let shift = |line: usize, col: usize| {
+            if line <= 1 {
+                (base.line, base.col + col - 1)
+            } else {
+                (base.line + line - 1, col)
+            }
+        };
+        let (line, col) = shift(self.line, self.col);
+        let (end_line, end_col) = shift(self.end_line, self.end_col);
+        Self {
+            byte_offset: base.byte_offset + self.byte_offset,
+            line,
+            col,
+            end_line,
+            end_col,
+            len: self.len,
+        }
+    }
+
+    /// Span covering the whole fragment.
+    pub fn from_fragment(x: NomSpan<'_>) -> Self {
+        use nom::Slice;
+
+        Self::from_start_end(x, x.slice(x.fragment().len()..))
+    }
 }
 
 impl From<(NomSpan<'_>, NomSpan<'_>)> for Span {
@@ -137,6 +178,10 @@ impl<T> Sp<T> {
     pub fn into_inner(self) -> T {
         self.0
     }
+
+    pub(crate) fn span_mut(&mut self) -> &mut crate::Span {
+        &mut self.1
+    }
 }
 
 pub(crate) trait WithSpan {
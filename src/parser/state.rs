@@ -6,8 +6,19 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "snake_case")]
 pub enum PWarning {
     DuplicateModifier(char, NoteType),
-    MultipleSlideTrackGroups,
     MissingSlideStartKey,
+    UnknownKey(String),
+    DuplicateKey {
+        key: String,
+        first: Span,
+    },
+    ActiveMessageCountMismatch {
+        times: usize,
+        contents: usize,
+    },
+    /// The last group of a chained slide without a duration, e.g. `1-4[8:1]q7`. It takes the
+    /// duration of the group before it, given here as written.
+    MissingChainedSlideDuration(String),
 }
 
 impl std::fmt::Display for PWarning {
@@ -16,12 +27,21 @@ impl std::fmt::Display for PWarning {
             PWarning::DuplicateModifier(c, t) => {
                 write!(f, "duplicate `{}` modifier in {} instruction", c, t)
             }
-            PWarning::MultipleSlideTrackGroups => {
-                write!(f, "multiple slide track groups in slide instruction")
-            }
             PWarning::MissingSlideStartKey => {
                 write!(f, "missing start key in slide instruction")
             }
+            PWarning::UnknownKey(k) => write!(f, "unknown key `{}`", k),
+            PWarning::DuplicateKey { key, .. } => write!(f, "duplicate key `{}`", key),
+            PWarning::ActiveMessageCountMismatch { times, contents } => write!(
+                f,
+                "{} active message timestamps but {} active message contents",
+                times, contents
+            ),
+            PWarning::MissingChainedSlideDuration(dur) => write!(
+                f,
+                "missing duration of chained slide group, using `[{}]` from the one before",
+                dur
+            ),
         }
     }
 }
@@ -31,25 +51,25 @@ impl std::fmt::Display for PWarning {
 pub enum PError {
     UnknownChar(char),
 
-    // TODO: rename
-    ExpectedBefore {
-        expected: String,
-        location: String,
+    /// `missing` is absent before `next`, e.g. `(` before the BPM value.
+    MissingBefore {
+        missing: String,
+        next: String,
     },
-    // TODO: rename
-    ExpectedAfter {
-        expected: String,
-        location: String,
+    /// `missing` is absent after `previous`, e.g. `=` after a key.
+    MissingAfter {
+        missing: String,
+        previous: String,
     },
-    // TODO: rename
-    ExpectedBetween {
-        expected: String,
+    MissingBetween {
+        missing: String,
         previous: String,
         next: String,
     },
 
-    // TODO: rename
-    MissingBeatsNum, // [divisor:num]
+    /// `[divisor:]` without the number of beats.
+    MissingBeatCount,
+    /// Only slides need a duration, holds and touch holds without one are zero-length.
     MissingDuration(NoteType),
     MissingNote,
     MissingSlideStartKey,
@@ -60,11 +80,20 @@ pub enum PError {
     InvalidBeatDivisor(String),
     InvalidDuration(String),
     InvalidSlideStopTime(String),
-    InvalidSlideTrack(String),
+    /// `track` is written without the start key `start`. `suggestion` replaces the track text if
+    /// the intent is obvious, e.g. `-1` for `1<1` instead of `1-1`.
+    InvalidSlideTrack {
+        start: String,
+        track: String,
+        suggestion: Option<String>,
+    },
+    InvalidOffset(String),
+    InvalidLevel(String),
+    InvalidActiveMessageTime(String),
+    /// A note that could not be parsed, up to the next `,`, `/` or `` ` ``.
+    InvalidNote(String),
 
     DuplicateShapeModifier(NoteType),
-    // TODO: rename
-    DurationMismatch(NoteType), // [4:1] + [#2.0]
 }
 
 impl std::fmt::Display for PError {
@@ -72,25 +101,19 @@ impl std::fmt::Display for PError {
         match self {
             PError::UnknownChar(c) => write!(f, "unknown character `{}`", c),
 
-            PError::ExpectedBefore {
-                expected,
-                location: after,
-            } => {
-                write!(f, "expected {} before {}", expected, after)
+            PError::MissingBefore { missing, next } => {
+                write!(f, "expected {} before {}", missing, next)
             }
-            PError::ExpectedAfter {
-                expected,
-                location: before,
-            } => {
-                write!(f, "expected {} after {}", expected, before)
+            PError::MissingAfter { missing, previous } => {
+                write!(f, "expected {} after {}", missing, previous)
             }
-            PError::ExpectedBetween {
-                expected,
-                previous: before,
-                next: after,
-            } => write!(f, "expected {} between {} and {}", expected, before, after),
+            PError::MissingBetween {
+                missing,
+                previous,
+                next,
+            } => write!(f, "expected {} between {} and {}", missing, previous, next),
 
-            PError::MissingBeatsNum => write!(f, "missing number of beats"),
+            PError::MissingBeatCount => write!(f, "missing number of beats"),
             PError::MissingDuration(t) => write!(f, "missing {} duration", t),
             PError::MissingNote => write!(f, "missing note"),
             PError::MissingSlideStartKey => write!(f, "missing slide start key"),
@@ -103,12 +126,19 @@ impl std::fmt::Display for PError {
             PError::InvalidBeatDivisor(s) => write!(f, "invalid beat divisor `{}`", s),
             PError::InvalidDuration(s) => write!(f, "invalid duration `{}`", s),
             PError::InvalidSlideStopTime(s) => write!(f, "invalid slide stop time {}", s),
-            PError::InvalidSlideTrack(s) => write!(f, "invalid slide track `{}`", s),
+            PError::InvalidSlideTrack { start, track, .. } => {
+                write!(f, "invalid slide track `{}{}`", start, track)
+            }
+            PError::InvalidOffset(s) => write!(f, "invalid offset `{}`", s),
+            PError::InvalidLevel(s) => write!(f, "invalid level `{}`", s),
+            PError::InvalidActiveMessageTime(s) => {
+                write!(f, "invalid active message time `{}`", s)
+            }
+            PError::InvalidNote(s) => write!(f, "invalid note `{}`", s),
 
             PError::DuplicateShapeModifier(t) => {
                 write!(f, "duplicate {} shape modifier", t)
             }
-            PError::DurationMismatch(t) => write!(f, "{} duration mismatch", t),
         }
     }
 }
@@ -139,4 +169,14 @@ impl State {
     pub fn has_messages(&self) -> bool {
         self.has_warnings() || self.has_errors()
     }
+
+    /// Numbers of messages recorded so far, for discarding later ones with [`State::rollback`].
+    pub(crate) fn checkpoint(&self) -> (usize, usize) {
+        (self.warnings.len(), self.errors.len())
+    }
+
+    pub(crate) fn rollback(&mut self, checkpoint: (usize, usize)) {
+        self.warnings.truncate(checkpoint.0);
+        self.errors.truncate(checkpoint.1);
+    }
 }
@@ -8,6 +8,21 @@ where
     nom::sequence::preceded(multispace0, f)
 }
 
+/// discard messages added by `f` if it fails, e.g. when backtracking in `alt`
+pub fn rollback_on_err<'a, F, O>(mut f: F) -> impl FnMut(NomSpan<'a>) -> PResult<'a, O>
+where
+    F: FnMut(NomSpan<'a>) -> PResult<'a, O>,
+{
+    move |i: NomSpan<'a>| {
+        let checkpoint = i.extra.borrow().checkpoint();
+        let result = f(i);
+        if result.is_err() {
+            i.extra.borrow_mut().rollback(checkpoint);
+        }
+        result
+    }
+}
+
 pub fn ws_list0<'a, F, O>(mut f: F) -> impl FnMut(NomSpan<'a>) -> PResult<'a, Vec<O>>
 where
     F: 'a + FnMut(NomSpan<'a>) -> PResult<'a, O>,
@@ -146,9 +161,9 @@ where
         if open.is_none() {
             let (_, end_loc) = nom_locate::position(i)?;
             i3.extra.borrow_mut().add_error(
-                PError::ExpectedBefore {
-                    expected: format!("`{}`", start),
-                    location: inner_name.to_string(),
+                PError::MissingBefore {
+                    missing: format!("`{}`", start),
+                    next: inner_name.to_string(),
                 },
                 (end_loc, end_loc).into(),
             );
@@ -156,8 +171,8 @@ where
         if result.is_none() {
             let (_, end_loc) = nom_locate::position(i1)?;
             i3.extra.borrow_mut().add_error(
-                PError::ExpectedBetween {
-                    expected: inner_name.to_string(),
+                PError::MissingBetween {
+                    missing: inner_name.to_string(),
                     previous: format!("`{}`", start),
                     next: format!("`{}`", end),
                 },
@@ -167,9 +182,9 @@ where
         if close.is_none() {
             let (_, end_loc) = nom_locate::position(i2)?;
             i3.extra.borrow_mut().add_error(
-                PError::ExpectedAfter {
-                    expected: format!("`{}`", end),
-                    location: inner_name.to_string(),
+                PError::MissingAfter {
+                    missing: format!("`{}`", end),
+                    previous: inner_name.to_string(),
                 },
                 (end_loc, end_loc).into(),
             );
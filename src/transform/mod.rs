@@ -1,5 +1,6 @@
 pub mod normalize;
 mod note_ty;
+#[allow(clippy::module_inception)]
 pub mod transform;
 
 pub use note_ty::*;
@@ -105,18 +105,16 @@ pub fn normalize_slide_segment(
 }
 
 pub fn normalize_slide_track(start: Key, track: &SlideTrack) -> Option<NormalizedSlideTrack> {
-    if track.segments.len() > 1
+    if track.segments().count() > 1
         && track
-            .segments
-            .iter()
+            .segments()
             .any(|segment| segment.shape() == SlideSegmentShape::Spread)
     {
         return None;
     }
     let mut start = start;
     track
-        .segments
-        .iter()
+        .segments()
         .map(|segment| {
             let result = normalize_slide_segment(start, segment);
             start = segment.params().destination;